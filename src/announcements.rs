@@ -0,0 +1,61 @@
+use chrono::Local;
+use cron::Schedule;
+use rocket::{
+    serde::{Deserialize, Serialize},
+    tokio::{self, select, sync::broadcast::Sender, time},
+    Shutdown,
+};
+
+use crate::{history::History, Message};
+
+// a message sent into a room on a schedule, like a standup reminder, e.g.
+//
+//   [[default.chat.announcements]]
+//   schedule = "0 55 9 * * Mon-Fri"
+//   room = "standup"
+//   message = "standup in 5 minutes"
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Announcement {
+    // a cron expression with a seconds field, in the server's local time. a
+    // malformed one stops the server from starting
+    pub schedule: Schedule,
+    pub room: String,
+    pub message: String,
+    // who it's posted as
+    #[serde(default = "default_username")]
+    pub username: String,
+}
+
+fn default_username() -> String {
+    "announcements".to_string()
+}
+
+// send each of `announcements` whenever it's due, until shutdown. they go
+// out like any other message, so they're kept for catching up too
+pub fn start(
+    announcements: &[Announcement],
+    history: &History,
+    queue: &Sender<Message>,
+    shutdown: Shutdown,
+) {
+    for announcement in announcements.iter().cloned() {
+        let (history, queue, mut shutdown) = (history.clone(), queue.clone(), shutdown.clone());
+        tokio::spawn(async move {
+            for due in announcement.schedule.upcoming(Local) {
+                let wait = (due - Local::now()).to_std().unwrap_or_default();
+                select! {
+                    _ = time::sleep(wait) => {},
+                    _ = &mut shutdown => break,
+                }
+
+                let mut message = Message::new(
+                    announcement.room.clone(),
+                    announcement.username.clone(),
+                    announcement.message.clone(),
+                );
+                history.publish(&queue, &mut message);
+            }
+        });
+    }
+}
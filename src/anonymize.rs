@@ -0,0 +1,53 @@
+// demo mode: swap real usernames for stable, made-up ones so screenshots and
+// public demos never show who's actually talking
+
+use crate::hash::fnv1a;
+
+const ADJECTIVES: [&str; 32] = [
+    "Amber", "Brave", "Calm", "Clever", "Cosmic", "Crimson", "Daring", "Dusty", "Eager", "Fancy",
+    "Fuzzy", "Gentle", "Golden", "Happy", "Hidden", "Jolly", "Lucky", "Mellow", "Misty", "Nimble",
+    "Plucky", "Quiet", "Rapid", "Rusty", "Shiny", "Silent", "Sleepy", "Snowy", "Sunny", "Swift",
+    "Witty", "Zesty",
+];
+
+const ANIMALS: [&str; 32] = [
+    "Badger", "Beaver", "Bison", "Cobra", "Crane", "Dingo", "Falcon", "Ferret", "Gecko", "Heron",
+    "Ibis", "Jackal", "Koala", "Lemur", "Lynx", "Marmot", "Moose", "Newt", "Ocelot", "Otter",
+    "Panda", "Puffin", "Quokka", "Raven", "Salmon", "Sloth", "Tapir", "Toucan", "Walrus", "Wombat",
+    "Yak", "Zebra",
+];
+
+// the pseudonym for `username`, e.g. "User-Sunny-Otter-3f2a". it only
+// depends on the name itself, so a user keeps theirs across posts, rooms and
+// restarts. the words alone would run out after a few dozen users, so the
+// hex suffix keeps different users apart: presence, mutes and room caps all
+// go by the pseudonym
+pub fn pseudonym(username: &str) -> String {
+    let hash = fnv1a(username.as_bytes());
+    let adjective = ADJECTIVES[(hash % ADJECTIVES.len() as u64) as usize];
+    let animal = ANIMALS[((hash >> 32) % ANIMALS.len() as u64) as usize];
+    let suffix = (hash >> 16) & 0xffff;
+    format!("User-{}-{}-{:04x}", adjective, animal, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn pseudonyms_are_stable() {
+        assert_eq!(pseudonym("alice"), pseudonym("alice"));
+        assert_ne!(pseudonym("alice"), pseudonym("bob"));
+        assert!(pseudonym("alice").starts_with("User-"));
+    }
+
+    #[test]
+    fn pseudonyms_keep_a_crowd_apart() {
+        let names: HashSet<_> = (0..1000)
+            .map(|n| pseudonym(&format!("user{}", n)))
+            .collect();
+        assert_eq!(names.len(), 1000);
+    }
+}
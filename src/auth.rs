@@ -0,0 +1,86 @@
+use rocket::{
+    http::Status,
+    outcome::Outcome,
+    request::{self, FromRequest, Request},
+    serde::{Deserialize, Serialize},
+};
+
+use crate::config::Config;
+
+// roles are ordered, so an admin can do everything a moderator can
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Member,
+    Moderator,
+    Admin,
+}
+
+// an account from the `accounts` table in the config, keyed by its token
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Account {
+    pub username: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+// a request carrying a known token in an `Authorization: Bearer` header
+#[derive(Debug, Clone)]
+pub struct User(pub Account);
+
+// a user with at least the moderator role
+#[derive(Debug, Clone)]
+pub struct Moderator(pub Account);
+
+// a user with the admin role
+#[derive(Debug, Clone)]
+pub struct Admin(pub Account);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for User {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let config = req.rocket().state::<Config>().expect("config is managed");
+        let account = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| config.accounts.get(token));
+
+        match account {
+            Some(account) => Outcome::Success(User(account.clone())),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+// the signed-in account, if it has at least `role`
+async fn require(req: &Request<'_>, role: Role) -> request::Outcome<Account, ()> {
+    match req.guard::<User>().await {
+        Outcome::Success(User(account)) if account.role >= role => Outcome::Success(account),
+        Outcome::Success(_) => Outcome::Error((Status::Forbidden, ())),
+        Outcome::Error(e) => Outcome::Error(e),
+        Outcome::Forward(s) => Outcome::Forward(s),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Moderator {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        require(req, Role::Moderator).await.map(Moderator)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        require(req, Role::Admin).await.map(Admin)
+    }
+}
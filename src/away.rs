@@ -0,0 +1,135 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{config::Config, limits::Names};
+
+// away notes, like an email vacation responder: while a user has one set,
+// anyone who @mentions them gets it back as an auto-reply only they can see
+pub struct Away {
+    inner: Mutex<Inner>,
+    names: Names,
+}
+
+#[derive(Default)]
+struct Inner {
+    // username -> their away note, and when they set it
+    notes: HashMap<String, (String, Instant)>,
+    // (sender, recipient) -> when the recipient last auto-replied to them
+    replied: HashMap<(String, String), Instant>,
+}
+
+impl Away {
+    pub fn new(config: &Config) -> Self {
+        Away {
+            inner: Mutex::default(),
+            names: Names::new(config),
+        }
+    }
+
+    pub fn set(&self, username: &str, note: String) {
+        let mut inner = self.inner.lock().unwrap();
+        self.names
+            .make_room(&mut inner.notes, username, |(_, at)| *at);
+        inner
+            .notes
+            .insert(username.to_string(), (note, Instant::now()));
+    }
+
+    // the user is back (or said so), so stop replying on their behalf
+    pub fn clear(&self, username: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.notes.remove(username);
+        inner
+            .replied
+            .retain(|(_, recipient), _| recipient != username);
+    }
+
+    // the first away user `matches` picks out
+    pub fn find(&self, matches: impl Fn(&str) -> bool) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        inner.notes.keys().find(|name| matches(name)).cloned()
+    }
+
+    // the note `recipient` should auto-reply to `sender` with, if they're
+    // away and haven't already replied to them within `cooldown`. two away
+    // users mentioning each other can't ping-pong, since auto-replies are
+    // never themselves checked for mentions
+    pub fn reply(&self, sender: &str, recipient: &str, cooldown: Duration) -> Option<String> {
+        let mut inner = self.inner.lock().unwrap();
+        let (note, _) = inner.notes.get(recipient)?.clone();
+
+        let now = Instant::now();
+        inner
+            .replied
+            .retain(|_, at| now.duration_since(*at) < cooldown);
+
+        let key = (sender.to_string(), recipient.to_string());
+        if inner.replied.contains_key(&key) {
+            return None;
+        }
+
+        inner.replied.insert(key, now);
+        Some(note)
+    }
+}
+
+// the usernames @mentioned in `text`, minus any trailing punctuation, so
+// "thanks @alice!" mentions "alice"
+pub fn mentions(text: &str) -> impl Iterator<Item = &str> {
+    text.split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches(|c: char| c.is_ascii_punctuation() && c != '_'))
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_drop_trailing_punctuation() {
+        let mentioned: Vec<_> = mentions("thanks @alice! and @bob_, cc @ @carol.").collect();
+        assert_eq!(mentioned, ["alice", "bob_", "carol"]);
+    }
+
+    #[test]
+    fn replies_go_once_per_sender_per_cooldown() {
+        let away = Away::new(&Config::default());
+        let cooldown = Duration::from_secs(60);
+        assert_eq!(away.reply("ann", "bob", cooldown), None);
+
+        away.set("bob", "on holiday".into());
+        assert_eq!(
+            away.reply("ann", "bob", cooldown).as_deref(),
+            Some("on holiday")
+        );
+        assert_eq!(away.reply("ann", "bob", cooldown), None);
+        assert!(away.reply("cat", "bob", cooldown).is_some());
+        assert!(away.reply("ann", "bob", Duration::ZERO).is_some());
+
+        away.clear("bob");
+        assert_eq!(away.reply("dan", "bob", cooldown), None);
+    }
+
+    #[test]
+    fn notes_without_an_account_are_capped() {
+        let config = Config {
+            max_anonymous_users: 2,
+            ..Config::default()
+        };
+        let away = Away::new(&config);
+        for name in ["ann", "ben", "cat"] {
+            away.set(name, "out".into());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        away.set("ben", "still out".into());
+
+        let cooldown = Duration::from_secs(60);
+        assert_eq!(away.reply("dan", "ann", cooldown), None);
+        assert!(away.reply("dan", "ben", cooldown).is_some());
+        assert!(away.reply("dan", "cat", cooldown).is_some());
+    }
+}
@@ -0,0 +1,498 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rocket::{
+    figment::Figment,
+    fs::relative,
+    serde::{Deserialize, Serialize, Serializer},
+    Route,
+};
+
+use crate::{announcements::Announcement, auth::Account, templates::Template};
+
+// chat settings live under a `chat` table in Rocket.toml, e.g.
+//
+//   [default.chat]
+//   continuation_window = 120
+//
+// anything left out falls back to the defaults below
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Config {
+    // how many messages the broadcast channel holds before subscribers that
+    // fall behind start missing some
+    pub channel_capacity: usize,
+    // fraction of the channel capacity a subscriber can fall behind by before
+    // it's sent a `slow_consumer` warning (0 disables)
+    pub slow_consumer_threshold: f64,
+    // seconds after a user's message that their next one in the same room
+    // still counts as a continuation (0 turns the flag off)
+    pub continuation_window: u64,
+    // how many recent messages (across all rooms) to keep for catching up
+    // clients that reconnect
+    pub history_size: usize,
+    // approximate bytes those messages may take up, so a run of huge ones
+    // can't balloon memory (0 to bound by count alone)
+    pub history_bytes: usize,
+    // include a `content_hash` on each message for clients to dedupe with
+    pub content_hashes: bool,
+    // include each message's `byte_len`, its length in bytes as json
+    pub byte_lengths: bool,
+    // replay missed messages on `/events` from the read cursors a client
+    // reported via `/read`
+    pub resume: bool,
+    // the most messages a client can ask `/events?backfill=` to send up front
+    pub max_backfill: usize,
+    // how many replayed messages `/events` sends before stepping aside for
+    // other tasks, so a big replay can't hog a worker; 0 to send them all in
+    // one go
+    pub replay_batch: usize,
+    // widest span one /history-range query can cover, in seconds, with any
+    // query for more cut short; 0 for no limit
+    pub max_range_span: u64,
+    // most messages one /history-range query returns; 0 for no limit
+    pub max_range_results: usize,
+    // the longest, in seconds, a `/poll` request waits for new messages. also
+    // the wait when the client doesn't ask for one
+    pub max_poll_timeout: u64,
+    // how many `/poll` requests one ip can have waiting at once (0 for no
+    // limit)
+    pub max_polls_per_ip: usize,
+    // bearer tokens and the accounts they sign in as, e.g.
+    //   [default.chat.accounts]
+    //   "s3cret" = { username = "alice", role = "moderator" }
+    #[serde(serialize_with = "redact_tokens")]
+    pub accounts: HashMap<String, Account>,
+    // resolve a coarse origin (country/region) for each post, visible to
+    // moderators only
+    pub geo_hints: bool,
+    // demo mode: replace every username with a stable pseudonym before it's
+    // broadcast or remembered. moderators can still see the real name
+    pub anonymize: bool,
+    // include the sender's role (member, moderator, admin) on each message
+    pub role_badges: bool,
+    // send `membership` events on /events when users join or leave a room.
+    // /presence-stream always sends them
+    pub membership_events: bool,
+    // seconds a signed-in /events or /firehose stream stays open before it
+    // has to reconnect and show its token again; 0 for no limit
+    pub max_stream_lifetime: u64,
+    // turn read cursor updates into `read_receipt` events for the room
+    pub read_receipts: bool,
+    // milliseconds between one user's receipts in a room, so scrolling
+    // through a backlog doesn't send one per message. the furthest read in
+    // between goes out when the time is up
+    pub receipt_interval_ms: u64,
+    // seconds between `room_stats` events on each room's streams; 0 disables
+    pub room_stats_interval: u64,
+    // seconds before an away user auto-replies to the same sender again
+    pub away_reply_cooldown: u64,
+    // most messages per second sent down each `/firehose` stream before the
+    // rest are skipped (0 for no limit)
+    pub firehose_rate_limit: u64,
+    // endpoints to leave unmounted, by handler name (`post`, `read`,
+    // `events`, `get_metrics`, ...). takes effect on restart
+    pub disabled_endpoints: Vec<String>,
+    // milliseconds a live message may take from being posted to being sent
+    // to a subscriber; compliance shows up in /metrics (0 stops timing)
+    pub latency_slo_ms: u64,
+    // NFKC-normalize the usernames people without an account pick
+    pub normalize_usernames: bool,
+    // turn away usernames that look like an account holder's, homoglyphs
+    // included, unless it's them signed in
+    pub protect_account_names: bool,
+    // turn away messages that would render as blank, like ones made only of
+    // zero-width or control characters
+    pub require_visible: bool,
+    // longest message allowed, in characters; 0 for no limit. rooms can set
+    // their own
+    pub max_message_length: usize,
+    // characters of a message shown in `/previews`
+    pub preview_length: usize,
+    // rooms one `/previews` request can ask about; 0 for no limit
+    pub max_preview_rooms: usize,
+    // users without an account whose /user-stats totals are kept, the one
+    // who posted least recently dropped first; 0 for no limit
+    pub max_anonymous_stats: usize,
+    // users without an account whose away notes, mutes and read receipt
+    // settings are each kept, the least recently set dropped first; 0 for
+    // no limit
+    pub max_anonymous_users: usize,
+    // seconds an unsent draft is kept for
+    pub draft_ttl: u64,
+    // drafts kept across all users, oldest evicted first; 0 for no limit
+    pub max_drafts: usize,
+    // turn away forms with fields we don't know about, rather than ignoring
+    // the extras
+    pub strict_forms: bool,
+    // answer a form that doesn't validate with a JSON list of every bad
+    // field, instead of Rocket's error page
+    pub form_error_details: bool,
+    // how many users each user can have muted at once; 0 for no limit
+    pub max_mutes: usize,
+    // how many users have to report a message before it's hidden from
+    // replays pending review, or 0 to never hide one
+    pub report_hide_threshold: usize,
+    // gap reports each ip can send a minute; 0 for no limit
+    pub gap_reports_per_minute: usize,
+    // ids a single gap report can cover; 0 for no limit
+    pub max_gap_span: u64,
+    // seconds a user has to wait between posts after one of their messages
+    // is hidden by reports, growing with each further one; 0 disables
+    pub flag_cooldown: u64,
+    // seconds that wait can grow to, however many flags pile up
+    pub max_flag_cooldown: u64,
+    // seconds for that heat to halve, as they behave; 0 never eases off
+    pub heat_half_life: u64,
+    // milliseconds `/selfcheck` waits for its probe to come back
+    pub selfcheck_timeout_ms: u64,
+    // serve the frontend from `static_dir`. turn off for api-only setups
+    pub serve_static: bool,
+    pub static_dir: PathBuf,
+    // refuse to launch if `static_dir` is missing, rather than warning and
+    // serving 404s. off by default, since the default directory is where
+    // the repo was built
+    pub require_static_dir: bool,
+    // settings for particular rooms, e.g.
+    //   [default.chat.rooms.support]
+    //   max_users = 10
+    pub rooms: HashMap<String, RoomConfig>,
+    // messages sent into rooms on a cron schedule
+    pub announcements: Vec<Announcement>,
+}
+
+// per-room settings; rooms not listed get the defaults
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RoomConfig {
+    // how many users can have streams open to the room at once (0 for no
+    // limit). streams have to give a username to be counted, so ones that
+    // don't are turned away; moderators aren't held to it
+    pub max_users: usize,
+    // turn away messages with line breaks, so each entry stays one line
+    pub single_line: bool,
+    // overrides the global `max_message_length` for this room
+    pub max_message_length: Option<usize>,
+    // a regex every message has to match in full, and an example of it to
+    // show whoever gets it wrong
+    pub template: Option<Template>,
+    pub template_hint: Option<String>,
+    // overrides the global `continuation_window` for this room
+    pub continuation_window: Option<u64>,
+    // this room's say on the global on/off switches; anything left out
+    // follows the global setting
+    pub features: Features,
+}
+
+// per-room overrides for the global switches of the same names. every
+// switch that decides something about one room's messages is here, except
+// `anonymize`: pseudonyms stand in for a user in mentions, away replies,
+// receipts and presence, which all cross rooms, so a user shown by name in
+// one room would be unmasked in every other
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", default, deny_unknown_fields)]
+pub struct Features {
+    pub role_badges: Option<bool>,
+    pub geo_hints: Option<bool>,
+    pub membership_events: Option<bool>,
+    pub read_receipts: Option<bool>,
+    pub content_hashes: Option<bool>,
+    pub byte_lengths: Option<bool>,
+    pub require_visible: Option<bool>,
+    pub resume: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Feature {
+    RoleBadges,
+    GeoHints,
+    MembershipEvents,
+    ReadReceipts,
+    ContentHashes,
+    ByteLengths,
+    RequireVisible,
+    Resume,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            channel_capacity: 1024,
+            slow_consumer_threshold: 0.8,
+            continuation_window: 60,
+            history_size: 1024,
+            history_bytes: 1024 * 1024,
+            content_hashes: true,
+            byte_lengths: false,
+            resume: true,
+            max_backfill: 100,
+            replay_batch: 64,
+            max_range_span: 7 * 86400,
+            max_range_results: 100,
+            max_poll_timeout: 30,
+            max_polls_per_ip: 4,
+            accounts: HashMap::new(),
+            geo_hints: false,
+            anonymize: false,
+            role_badges: true,
+            membership_events: true,
+            max_stream_lifetime: 3600,
+            read_receipts: true,
+            receipt_interval_ms: 1000,
+            room_stats_interval: 30,
+            away_reply_cooldown: 600,
+            firehose_rate_limit: 200,
+            disabled_endpoints: Vec::new(),
+            latency_slo_ms: 100,
+            normalize_usernames: true,
+            protect_account_names: true,
+            require_visible: true,
+            max_message_length: 0,
+            preview_length: 80,
+            max_preview_rooms: 50,
+            max_anonymous_stats: 10000,
+            max_anonymous_users: 10000,
+            draft_ttl: 86400,
+            max_drafts: 10000,
+            strict_forms: false,
+            form_error_details: true,
+            max_mutes: 100,
+            report_hide_threshold: 3,
+            gap_reports_per_minute: 6,
+            max_gap_span: 1000,
+            flag_cooldown: 10,
+            max_flag_cooldown: 3600,
+            heat_half_life: 600,
+            selfcheck_timeout_ms: 1000,
+            serve_static: true,
+            static_dir: PathBuf::from(relative!("static")),
+            require_static_dir: false,
+            rooms: HashMap::new(),
+            announcements: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // pull the `chat` table out of rocket's figment. a bad config should stop
+    // the launch, same as rocket does for its own settings
+    pub fn from_figment(figment: &Figment) -> Config {
+        figment.focus("chat").extract().unwrap_or_else(|e| {
+            rocket::config::pretty_print_error(e);
+            panic!("aborting due to invalid chat configuration")
+        })
+    }
+
+    pub fn away_reply_cooldown(&self) -> Duration {
+        Duration::from_secs(self.away_reply_cooldown)
+    }
+
+    pub fn draft_ttl(&self) -> Duration {
+        Duration::from_secs(self.draft_ttl)
+    }
+
+    pub fn max_stream_lifetime(&self) -> Option<Duration> {
+        match self.max_stream_lifetime {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn receipt_interval(&self) -> Duration {
+        Duration::from_millis(self.receipt_interval_ms)
+    }
+
+    pub fn flag_cooldown(&self) -> Option<Duration> {
+        match self.flag_cooldown {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn max_flag_cooldown(&self) -> Duration {
+        Duration::from_secs(self.max_flag_cooldown)
+    }
+
+    pub fn heat_half_life(&self) -> Duration {
+        Duration::from_secs(self.heat_half_life)
+    }
+
+    pub fn room_stats_interval(&self) -> Option<Duration> {
+        match self.room_stats_interval {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn latency_slo(&self) -> Option<Duration> {
+        match self.latency_slo_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    pub fn room(&self, name: &str) -> RoomConfig {
+        self.rooms.get(name).cloned().unwrap_or_default()
+    }
+
+    // whether `feature` is on in `room`: its own setting if it has one,
+    // otherwise the global one
+    pub fn feature(&self, room: &str, feature: Feature) -> bool {
+        let features = self.rooms.get(room).map(|room| &room.features);
+        let (room, global) = match feature {
+            Feature::RoleBadges => (features.and_then(|f| f.role_badges), self.role_badges),
+            Feature::GeoHints => (features.and_then(|f| f.geo_hints), self.geo_hints),
+            Feature::MembershipEvents => (
+                features.and_then(|f| f.membership_events),
+                self.membership_events,
+            ),
+            Feature::ReadReceipts => (features.and_then(|f| f.read_receipts), self.read_receipts),
+            Feature::ContentHashes => {
+                (features.and_then(|f| f.content_hashes), self.content_hashes)
+            }
+            Feature::ByteLengths => (features.and_then(|f| f.byte_lengths), self.byte_lengths),
+            Feature::RequireVisible => (
+                features.and_then(|f| f.require_visible),
+                self.require_visible,
+            ),
+            Feature::Resume => (features.and_then(|f| f.resume), self.resume),
+        };
+        room.unwrap_or(global)
+    }
+
+    // how long after someone's message in `room` their next one there still
+    // continues it, if runs are on there at all
+    pub fn continuation_window(&self, room: &str) -> Option<Duration> {
+        let window = self
+            .rooms
+            .get(room)
+            .and_then(|room| room.continuation_window);
+        match window.unwrap_or(self.continuation_window) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    // the longest message `room` takes, in characters, if there's a limit
+    pub fn max_message_length(&self, room: &str) -> Option<usize> {
+        let max = self.room(room).max_message_length;
+        match max.unwrap_or(self.max_message_length) {
+            0 => None,
+            max => Some(max),
+        }
+    }
+
+    // where the frontend is served from, if it's served at all
+    pub fn static_dir(&self) -> Option<&Path> {
+        self.serve_static.then_some(self.static_dir.as_path())
+    }
+
+    pub fn selfcheck_timeout(&self) -> Duration {
+        Duration::from_millis(self.selfcheck_timeout_ms)
+    }
+
+    // the routes left once `disabled_endpoints` are taken out. a name that
+    // doesn't match any route is almost certainly a typo that would leave an
+    // endpoint exposed, so it stops the launch
+    pub fn enabled_routes(&self, mut routes: Vec<Route>) -> Vec<Route> {
+        for name in &self.disabled_endpoints {
+            if !routes
+                .iter()
+                .any(|route| route.name.as_deref() == Some(name))
+            {
+                panic!(
+                    "aborting: unknown endpoint `{}` in disabled_endpoints",
+                    name
+                );
+            }
+        }
+
+        routes.retain(|route| {
+            let name = route.name.as_deref().unwrap_or_default();
+            !self
+                .disabled_endpoints
+                .iter()
+                .any(|disabled| disabled == name)
+        });
+        routes
+    }
+}
+
+// the accounts as a list, without the tokens that key them. those are the
+// only secrets in the chat config, and anyone who can read them can sign in
+// as that account
+fn redact_tokens<S: Serializer>(
+    accounts: &HashMap<String, Account>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut accounts: Vec<_> = accounts.values().collect();
+    accounts.sort_by(|a, b| a.username.cmp(&b.username));
+    serializer.collect_seq(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_tokens_never_serialize() {
+        let mut config = Config::default();
+        let alice = Account {
+            username: "alice".to_string(),
+            role: Default::default(),
+        };
+        config.accounts.insert("s3cret".to_string(), alice);
+
+        let json = rocket::serde::json::to_string(&config).unwrap();
+        assert!(!json.contains("s3cret"), "{json}");
+        assert!(json.contains("alice"), "{json}");
+    }
+
+    #[test]
+    fn rooms_override_global_features() {
+        let mut config = Config {
+            geo_hints: false,
+            ..Config::default()
+        };
+        let quiet = Features {
+            role_badges: Some(false),
+            geo_hints: Some(true),
+            ..Features::default()
+        };
+        config.rooms.insert(
+            "quiet".to_string(),
+            RoomConfig {
+                features: quiet,
+                ..RoomConfig::default()
+            },
+        );
+
+        assert!(config.feature("lobby", Feature::RoleBadges));
+        assert!(!config.feature("quiet", Feature::RoleBadges));
+        assert!(!config.feature("lobby", Feature::GeoHints));
+        assert!(config.feature("quiet", Feature::GeoHints));
+        // anything the room leaves unset follows the global switch
+        assert!(config.feature("quiet", Feature::ReadReceipts));
+    }
+
+    #[test]
+    fn rooms_override_the_continuation_window() {
+        let mut config = Config::default();
+        let room = |continuation_window| RoomConfig {
+            continuation_window,
+            ..RoomConfig::default()
+        };
+        config.rooms.insert("slow".to_string(), room(Some(600)));
+        config.rooms.insert("off".to_string(), room(Some(0)));
+        config.rooms.insert("plain".to_string(), room(None));
+
+        let window = |room| config.continuation_window(room);
+        assert_eq!(window("slow"), Some(Duration::from_secs(600)));
+        assert_eq!(window("off"), None);
+        assert_eq!(window("plain"), Some(Duration::from_secs(60)));
+        assert_eq!(window("lobby"), Some(Duration::from_secs(60)));
+    }
+}
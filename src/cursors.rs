@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+
+use rocket::{http::CookieJar, serde::json};
+
+const COOKIE: &str = "cursors";
+
+// read cursors: the id of the last message a client has seen in each room.
+// they ride along in a private (encrypted and signed) cookie, so a returning
+// client resumes where it left off without tracking ids itself
+#[derive(Default)]
+pub struct Cursors(HashMap<String, u64>);
+
+impl Cursors {
+    // a missing, tampered or unreadable cookie just means no cursors yet
+    pub fn from_cookies(cookies: &CookieJar<'_>) -> Self {
+        cookies
+            .get_private(COOKIE)
+            .and_then(|cookie| json::from_str(cookie.value()).ok())
+            .map(Cursors)
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, cookies: &CookieJar<'_>) {
+        let value = json::to_string(&self.0).expect("cursors serialize");
+        cookies.add_private((COOKIE, value));
+    }
+
+    // cursors only ever move forward, so a late or repeated report can't
+    // rewind a room
+    pub fn advance(&mut self, room: &str, id: u64) {
+        let cursor = self.0.entry(room.to_string()).or_default();
+        *cursor = (*cursor).max(id);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.0.iter().map(|(room, id)| (room.as_str(), *id))
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rocket::serde::Serialize;
+
+use crate::now_millis;
+
+// in-progress messages, one per user per room, so a closed tab doesn't lose
+// what they were typing. they're only ever handed back to their owner. the
+// store is bounded by a count and drafts expire after a while; either way the
+// oldest go first
+#[derive(Default)]
+pub struct Drafts(Mutex<HashMap<(String, String), Saved>>);
+
+struct Saved {
+    draft: Draft,
+    at: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Draft {
+    pub room: String,
+    pub message: String,
+    // milliseconds since the unix epoch
+    pub saved_at: u64,
+}
+
+impl Drafts {
+    // keep `message` as `username`'s draft in `room`, replacing any earlier
+    // one, or drop it if `message` is empty. with `limit` drafts already
+    // stored (0 for no limit) the oldest is evicted to make room
+    pub fn save(&self, username: &str, room: &str, message: String, ttl: Duration, limit: usize) {
+        let mut drafts = self.0.lock().unwrap();
+        let key = (username.to_string(), room.to_string());
+        if message.is_empty() {
+            drafts.remove(&key);
+            return;
+        }
+
+        let now = Instant::now();
+        drafts.retain(|_, saved| now.duration_since(saved.at) < ttl);
+        if limit > 0 && drafts.len() >= limit && !drafts.contains_key(&key) {
+            let oldest = drafts
+                .iter()
+                .min_by_key(|(_, saved)| saved.at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                drafts.remove(&oldest);
+            }
+        }
+
+        let draft = Draft {
+            room: room.to_string(),
+            message,
+            saved_at: now_millis(),
+        };
+        drafts.insert(key, Saved { draft, at: now });
+    }
+
+    // `username`'s draft in `room`, unless it's older than `ttl`
+    pub fn get(&self, username: &str, room: &str, ttl: Duration) -> Option<Draft> {
+        let drafts = self.0.lock().unwrap();
+        drafts
+            .get(&(username.to_string(), room.to_string()))
+            .filter(|saved| saved.at.elapsed() < ttl)
+            .map(|saved| saved.draft.clone())
+    }
+
+    // they sent it, so there's nothing left to restore
+    pub fn clear(&self, username: &str, room: &str) {
+        let mut drafts = self.0.lock().unwrap();
+        drafts.remove(&(username.to_string(), room.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(86400);
+
+    #[test]
+    fn drafts_expire() {
+        let drafts = Drafts::default();
+        let ttl = Duration::from_millis(50);
+        drafts.save("ann", "lobby", "half a tho".to_string(), ttl, 0);
+        assert_eq!(
+            drafts.get("ann", "lobby", ttl).unwrap().message,
+            "half a tho"
+        );
+
+        thread::sleep(ttl);
+        assert!(drafts.get("ann", "lobby", ttl).is_none());
+    }
+
+    #[test]
+    fn the_oldest_draft_makes_room() {
+        let drafts = Drafts::default();
+        drafts.save("ann", "lobby", "one".to_string(), DAY, 2);
+        drafts.save("ann", "support", "two".to_string(), DAY, 2);
+        drafts.save("ann", "support", "two again".to_string(), DAY, 2);
+        assert!(drafts.get("ann", "lobby", DAY).is_some());
+
+        drafts.save("ben", "lobby", "three".to_string(), DAY, 2);
+        assert!(drafts.get("ann", "lobby", DAY).is_none());
+        assert_eq!(
+            drafts.get("ann", "support", DAY).unwrap().message,
+            "two again"
+        );
+        assert!(drafts.get("ben", "lobby", DAY).is_some());
+    }
+
+    #[test]
+    fn an_empty_draft_discards_it() {
+        let drafts = Drafts::default();
+        drafts.save("ann", "lobby", "one".to_string(), DAY, 0);
+        drafts.save("ann", "lobby", String::new(), DAY, 0);
+        assert!(drafts.get("ann", "lobby", DAY).is_none());
+    }
+}
@@ -0,0 +1,110 @@
+use std::ops::Deref;
+
+use rocket::{
+    data::{self, Data, FromData},
+    form::{self, Errors, Form, FromForm, Strict},
+    http::Status,
+    outcome::Outcome,
+    serde::{
+        json::{json, Value},
+        Serialize,
+    },
+    Request,
+};
+
+use crate::config::Config;
+
+// a form parsed the way the config's `strict_forms` says. lenient (the
+// default) ignores fields it doesn't know, so older or third-party clients
+// sending extras keep working; strict turns them away. known fields are
+// validated either way
+#[derive(Debug)]
+pub struct ChatForm<T>(T);
+
+impl<T> ChatForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ChatForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: FromForm<'r> + Send> FromData<'r> for ChatForm<T> {
+    type Error = Errors<'r>;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let config = req.rocket().state::<Config>().expect("config is managed");
+        let outcome = match config.strict_forms {
+            true => Form::<Strict<T>>::from_data(req, data)
+                .await
+                .map(|form| ChatForm(form.into_inner().into_inner())),
+            false => Form::<T>::from_data(req, data)
+                .await
+                .map(|form| ChatForm(form.into_inner())),
+        };
+
+        // catchers can't see why a guard failed, so leave it for them here
+        if let Outcome::Error((_, errors)) = &outcome {
+            req.local_cache(|| FieldErrors(errors.iter().map(FieldError::from).collect()));
+        }
+        outcome
+    }
+}
+
+// one form field that didn't validate, and why
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FieldError {
+    // `None` for problems with the form as a whole
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl From<&form::Error<'_>> for FieldError {
+    fn from(error: &form::Error<'_>) -> Self {
+        FieldError {
+            field: error.name.as_ref().map(ToString::to_string),
+            message: error.kind.to_string(),
+        }
+    }
+}
+
+// the field errors from the request's form, if it had one that failed
+struct FieldErrors(Vec<FieldError>);
+
+// every field that failed validation at once, as `{"errors": [{field,
+// message}]}`, so clients can point out all of them rather than the first.
+// registered when the config's `form_error_details` is on. rocket answers
+// with a 413 rather than a 422 when a field is too long, so that gets the
+// same treatment
+#[catch(422)]
+pub fn unprocessable(req: &Request<'_>) -> Value {
+    field_errors(req)
+}
+
+#[catch(413)]
+pub fn too_large(req: &Request<'_>) -> Value {
+    field_errors(req)
+}
+
+fn field_errors(req: &Request<'_>) -> Value {
+    let FieldErrors(errors) = req.local_cache(|| FieldErrors(Vec::new()));
+    json!({ "errors": errors })
+}
+
+// a 422 for a field that parsed fine but still isn't acceptable, in the same
+// shape as the errors above
+pub fn invalid(field: &str, message: impl Into<String>) -> (Status, Value) {
+    let error = FieldError {
+        field: Some(field.to_string()),
+        message: message.into(),
+    };
+    (Status::UnprocessableEntity, json!({ "errors": [error] }))
+}
@@ -0,0 +1,27 @@
+use std::net::IpAddr;
+
+use rocket::serde::Serialize;
+
+// where a message came from, as coarse as we're willing to keep: a country
+// and maybe a region. never an address or anything finer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Geo {
+    pub country: String,
+    pub region: Option<String>,
+}
+
+// looks up a coarse origin for a poster's ip. plug in a real database by
+// managing a different `Box<dyn GeoResolver>` before `app` sets up the rest
+pub trait GeoResolver: Send + Sync {
+    fn resolve(&self, ip: IpAddr) -> Option<Geo>;
+}
+
+// the default resolver, which knows nothing
+pub struct NoGeo;
+
+impl GeoResolver for NoGeo {
+    fn resolve(&self, _ip: IpAddr) -> Option<Geo> {
+        None
+    }
+}
@@ -0,0 +1,58 @@
+use crate::Message;
+
+// a tiny fixed hash (64-bit FNV-1a). std's hasher isn't guaranteed to stay
+// the same between releases, and these values need to
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// a stable fingerprint of what a message says and where and when, for
+// clients to dedupe with when the same message reaches them twice. each
+// field is length-prefixed so ("ab", "c") and ("a", "bc") hash apart
+pub fn content_hash(message: &Message) -> String {
+    let mut bytes = Vec::new();
+    for field in [&message.room, &message.username, &message.message] {
+        bytes.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(field.as_bytes());
+    }
+    bytes.extend_from_slice(&message.timestamp.to_le_bytes());
+
+    format!("{:016x}", fnv1a(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(room: &str, username: &str, text: &str, timestamp: u64) -> Message {
+        let mut message = Message::new(room.into(), username.into(), text.into());
+        message.timestamp = timestamp;
+        message
+    }
+
+    #[test]
+    fn hashes_are_stable() {
+        // the known FNV-1a test vector
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(
+            content_hash(&message("a", "ann", "hi", 1)),
+            content_hash(&message("a", "ann", "hi", 1))
+        );
+    }
+
+    #[test]
+    fn every_field_changes_the_hash() {
+        let hash = content_hash(&message("a", "ann", "hi", 1));
+        assert_ne!(hash, content_hash(&message("b", "ann", "hi", 1)));
+        assert_ne!(hash, content_hash(&message("a", "ben", "hi", 1)));
+        assert_ne!(hash, content_hash(&message("a", "ann", "ho", 1)));
+        assert_ne!(hash, content_hash(&message("a", "ann", "hi", 2)));
+        // fields don't run into each other
+        assert_ne!(
+            content_hash(&message("ab", "c", "hi", 1)),
+            content_hash(&message("a", "bc", "hi", 1))
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// how much trouble a user has been in lately. each flag adds one, and it
+// halves every `half_life`, so someone who behaves drifts back to normal
+#[derive(Debug, Clone, Copy)]
+pub struct Heat {
+    level: f64,
+    at: Instant,
+}
+
+impl Heat {
+    pub fn new(now: Instant) -> Self {
+        Heat {
+            level: 0.0,
+            at: now,
+        }
+    }
+
+    pub fn level(&self, now: Instant, half_life: Duration) -> f64 {
+        if half_life.is_zero() {
+            return self.level;
+        }
+        let halvings =
+            now.saturating_duration_since(self.at).as_secs_f64() / half_life.as_secs_f64();
+        self.level * 0.5f64.powf(halvings)
+    }
+
+    pub fn flag(&mut self, now: Instant, half_life: Duration) {
+        self.level = self.level(now, half_life) + 1.0;
+        self.at = now;
+    }
+
+    // the wait between posts at this heat: nothing when cold, `base` after
+    // one flag, three times that after two, and so on, doubling as it
+    // escalates, but never past `max`
+    pub fn cooldown(
+        &self,
+        now: Instant,
+        half_life: Duration,
+        base: Duration,
+        max: Duration,
+    ) -> Duration {
+        let factor = 2f64.powf(self.level(now, half_life)) - 1.0;
+        Duration::try_from_secs_f64(base.as_secs_f64() * factor)
+            .unwrap_or(max)
+            .min(max)
+    }
+}
+
+// each user's heat, and when they last posted, for holding flagged users to
+// a slower pace
+#[derive(Default)]
+pub struct Cooldowns(Mutex<HashMap<String, (Heat, Option<Instant>)>>);
+
+impl Cooldowns {
+    pub fn flag(&self, username: &str, half_life: Duration) {
+        let mut users = self.0.lock().unwrap();
+        let now = Instant::now();
+        let (heat, _) = users
+            .entry(username.to_string())
+            .or_insert((Heat::new(now), None));
+        heat.flag(now, half_life);
+    }
+
+    // note a post from `username`, or `Err` with how much longer they have
+    // to wait if they're still cooling down. users who have cooled off are
+    // forgotten
+    pub fn post(
+        &self,
+        username: &str,
+        half_life: Duration,
+        base: Duration,
+        max: Duration,
+    ) -> Result<(), Duration> {
+        let mut users = self.0.lock().unwrap();
+        let now = Instant::now();
+        let Some((heat, last)) = users.get_mut(username) else {
+            return Ok(());
+        };
+
+        let cooldown = heat.cooldown(now, half_life, base, max);
+        if let Some(last) = last {
+            let since = now.duration_since(*last);
+            if since < cooldown {
+                return Err(cooldown - since);
+            }
+        }
+
+        if heat.level(now, half_life) < 0.01 {
+            users.remove(username);
+        } else {
+            *last = Some(now);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF_LIFE: Duration = Duration::from_secs(600);
+    const BASE: Duration = Duration::from_secs(10);
+    const MAX: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn cold_users_wait_for_nothing() {
+        let now = Instant::now();
+        assert_eq!(
+            Heat::new(now).cooldown(now, HALF_LIFE, BASE, MAX),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn flags_escalate_the_cooldown() {
+        let now = Instant::now();
+        let mut heat = Heat::new(now);
+        let waits: Vec<_> = (0..3)
+            .map(|_| {
+                heat.flag(now, HALF_LIFE);
+                heat.cooldown(now, HALF_LIFE, BASE, MAX).as_secs()
+            })
+            .collect();
+        assert_eq!(waits, [10, 30, 70]);
+    }
+
+    #[test]
+    fn heat_decays_back_to_normal() {
+        let now = Instant::now();
+        let mut heat = Heat::new(now);
+        heat.flag(now, HALF_LIFE);
+        heat.flag(now, HALF_LIFE);
+        assert_eq!(heat.level(now + HALF_LIFE, HALF_LIFE), 1.0);
+        assert!(heat.level(now + HALF_LIFE * 20, HALF_LIFE) < 0.01);
+        assert!(heat.cooldown(now + HALF_LIFE * 20, HALF_LIFE, BASE, MAX) < Duration::from_secs(1));
+        assert_eq!(heat.level(now + HALF_LIFE, Duration::ZERO), 2.0);
+    }
+
+    #[test]
+    fn the_cooldown_stops_growing_at_the_max() {
+        let now = Instant::now();
+        let mut heat = Heat::new(now);
+        for _ in 0..2000 {
+            heat.flag(now, HALF_LIFE);
+        }
+        assert_eq!(heat.cooldown(now, HALF_LIFE, BASE, MAX), MAX);
+    }
+
+    #[test]
+    fn a_boiling_user_keeps_the_lock_usable() {
+        let cooldowns = Cooldowns::default();
+        for _ in 0..100 {
+            cooldowns.flag("ann", HALF_LIFE);
+        }
+        assert_eq!(cooldowns.post("ann", HALF_LIFE, BASE, MAX), Ok(()));
+        assert_eq!(
+            cooldowns
+                .post("ann", HALF_LIFE, BASE, MAX)
+                .unwrap_err()
+                .as_secs(),
+            3599
+        );
+        assert_eq!(cooldowns.post("ben", HALF_LIFE, BASE, MAX), Ok(()));
+    }
+}
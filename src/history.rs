@@ -0,0 +1,532 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use rocket::{
+    serde::json,
+    tokio::sync::broadcast::{Receiver, Sender},
+};
+
+use crate::{
+    config::{Config, Feature},
+    hash, now_millis,
+    stats::{Stats, UserStats},
+    Message,
+};
+
+// the most recent messages across all rooms, so clients that drop off can be
+// caught up on what they missed. nothing is written to disk; once a message
+// falls out of the buffer it's gone. the buffer is bounded both by a count
+// and by the approximate bytes its messages take up, whichever is hit first.
+//
+// messages are stamped and broadcast under the same lock that subscribing
+// takes, so a subscriber's fence splits them exactly: everything up to it
+// was sent before they subscribed and can only come from a replay, and
+// everything after it arrives live. clones share the same buffer
+#[derive(Clone)]
+pub struct History {
+    inner: Arc<Mutex<Inner>>,
+    capacity: usize,
+    byte_budget: usize,
+    // for the switches rooms can override: content hashes, byte lengths and
+    // the continuation window
+    config: Arc<Config>,
+}
+
+struct Inner {
+    next_id: u64,
+    // rooms with messages in the buffer. a room is forgotten once its last
+    // one is evicted, so made-up room names can't pile up here
+    rooms: HashMap<String, Room>,
+    // the newest timestamp of any forgotten room, where a room that comes
+    // back starts from so its timestamps still only go up
+    floor: u64,
+    // each message with its serialized size
+    messages: VecDeque<(Message, usize)>,
+    bytes: usize,
+    // totals for everything ever published, not just what's still buffered
+    stats: Stats,
+}
+
+struct Room {
+    // the last timestamp handed out in the room
+    timestamp: u64,
+    // who was last published in the room, and at what timestamp
+    sender: Option<(String, u64)>,
+    // how many of its messages are in the buffer
+    kept: usize,
+}
+
+impl Inner {
+    fn forget_if_empty(&mut self, room: &str) {
+        if self.rooms.get(room).is_some_and(|state| state.kept == 0) {
+            if let Some(state) = self.rooms.remove(room) {
+                self.floor = self.floor.max(state.timestamp);
+            }
+        }
+    }
+}
+
+impl History {
+    pub fn new(config: &Config) -> Self {
+        History {
+            inner: Arc::new(Mutex::new(Inner {
+                next_id: 1,
+                rooms: HashMap::new(),
+                floor: 0,
+                messages: VecDeque::with_capacity(config.history_size),
+                bytes: 0,
+                stats: Stats::new(config),
+            })),
+            capacity: config.history_size,
+            // 0 leaves only the count limit
+            byte_budget: config.history_bytes,
+            config: Arc::new(config.clone()),
+        }
+    }
+
+    // stamp `message` and broadcast it without keeping it, for messages
+    // that shouldn't be replayed to anyone. they're not part of the room's
+    // conversation, so they never continue or break a run
+    pub fn deliver(&self, queue: &Sender<Message>, message: &mut Message) {
+        let mut inner = self.inner.lock().unwrap();
+        self.stamp(&mut inner, message, false);
+        Self::send(queue, message);
+        inner.forget_if_empty(&message.room);
+    }
+
+    // stamp `message`, keep a copy of it and broadcast it, evicting the
+    // oldest messages until it fits. one that's over the byte budget on its
+    // own is broadcast but never kept
+    pub fn publish(&self, queue: &Sender<Message>, message: &mut Message) {
+        let mut inner = self.inner.lock().unwrap();
+        self.stamp(&mut inner, message, true);
+        Self::send(queue, message);
+        inner.stats.record(message);
+
+        let size = json::to_string(message).map_or(0, |json| json.len());
+        let over_budget = self.byte_budget > 0 && size > self.byte_budget;
+        if self.capacity == 0 || over_budget {
+            inner.forget_if_empty(&message.room);
+            return;
+        }
+
+        // counted before making space, so evicting the room's older
+        // messages doesn't make it forget the one just stamped
+        if let Some(room) = inner.rooms.get_mut(&message.room) {
+            room.kept += 1;
+        }
+        while inner.messages.len() >= self.capacity
+            || (self.byte_budget > 0 && inner.bytes + size > self.byte_budget)
+        {
+            let Some((evicted, evicted_size)) = inner.messages.pop_front() else {
+                break;
+            };
+            inner.bytes -= evicted_size;
+            if let Some(room) = inner.rooms.get_mut(&evicted.room) {
+                room.kept -= 1;
+            }
+            inner.forget_if_empty(&evicted.room);
+        }
+
+        inner.bytes += size;
+        inner.messages.push_back((message.clone(), size));
+    }
+
+    // start receiving from `queue`, along with the fence: the last id sent
+    // before the receiver existed. replay what's wanted up to and including
+    // it, and take only what's after it from the receiver
+    pub fn subscribe(&self, queue: &Sender<Message>) -> (Receiver<Message>, u64) {
+        let inner = self.inner.lock().unwrap();
+        (queue.subscribe(), inner.next_id - 1)
+    }
+
+    // the last id handed out, or 0 before the first message
+    pub fn last_id(&self) -> u64 {
+        self.inner.lock().unwrap().next_id - 1
+    }
+
+    // ids and timestamps are handed out together under the lock, so sorting
+    // a room by timestamp gives the same order as sorting it by id. two
+    // messages landing in the same millisecond get bumped apart rather than
+    // tied, keeping timestamps strictly increasing within a room. whether a
+    // published message continues a run is settled here too, so it agrees
+    // with that order, and so does everything that covers the timestamp
+    fn stamp(&self, inner: &mut Inner, message: &mut Message, published: bool) {
+        message.id = inner.next_id;
+        inner.next_id += 1;
+
+        let now = now_millis();
+        let floor = inner.floor;
+        let room = inner
+            .rooms
+            .entry(message.room.clone())
+            .or_insert_with(|| Room {
+                timestamp: floor,
+                sender: None,
+                kept: 0,
+            });
+        message.timestamp = now.max(room.timestamp + 1);
+        room.timestamp = message.timestamp;
+
+        // only published messages count towards runs, and a cross-post is a
+        // copy, so it starts one rather than continuing one
+        message.continuation = false;
+        if published {
+            let window = self.config.continuation_window(&message.room);
+            let previous = room
+                .sender
+                .replace((message.username.clone(), message.timestamp));
+            message.continuation = message.crossposted_from.is_none()
+                && previous
+                    .zip(window)
+                    .is_some_and(|((username, at), window)| {
+                        username == message.username
+                            && message.timestamp - at <= window.as_millis() as u64
+                    });
+        }
+
+        let feature = |feature| self.config.feature(&message.room, feature);
+        message.content_hash = feature(Feature::ContentHashes).then(|| hash::content_hash(message));
+        // last, so it measures everything else that goes out with it
+        message.byte_len = None;
+        if feature(Feature::ByteLengths) {
+            message.byte_len = Some(serialized_len(message));
+        }
+    }
+
+    fn send(queue: &Sender<Message>, message: &mut Message) {
+        message.sent_at = Some(Instant::now());
+        // nobody listening is fine
+        let _res = queue.send(message.clone());
+    }
+
+    // every buffered message newer than `id`, in `room` if given, oldest first
+    pub fn since(&self, room: Option<&str>, id: u64) -> Vec<Message> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .messages
+            .iter()
+            .map(|(msg, _)| msg)
+            .filter(|msg| msg.id > id && room.is_none_or(|room| msg.room == room))
+            .cloned()
+            .collect()
+    }
+
+    // up to `limit` buffered messages in `room` timestamped `from` to `to`
+    // inclusive that pass `keep`, oldest first, and whether there were more
+    // past them
+    pub fn range(
+        &self,
+        room: &str,
+        (from, to): (u64, u64),
+        limit: usize,
+        keep: impl Fn(&Message) -> bool,
+    ) -> (Vec<Message>, bool) {
+        let inner = self.inner.lock().unwrap();
+        let mut matching = inner
+            .messages
+            .iter()
+            .map(|(msg, _)| msg)
+            .filter(|msg| msg.room == room && (from..=to).contains(&msg.timestamp))
+            .filter(|msg| keep(msg));
+
+        let messages = matching.by_ref().take(limit).cloned().collect();
+        (messages, matching.next().is_some())
+    }
+
+    // the buffered message with `id`, if it's still around
+    pub fn get(&self, id: u64) -> Option<Message> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .messages
+            .iter()
+            .map(|(msg, _)| msg)
+            .find(|msg| msg.id == id)
+            .cloned()
+    }
+
+    // `username`'s totals across everything they've published
+    pub fn user_stats(&self, username: &str) -> Option<UserStats> {
+        self.inner.lock().unwrap().stats.get(username)
+    }
+
+    // the newest buffered message in `room` that passes `keep`
+    pub fn latest(&self, room: &str, keep: impl Fn(&Message) -> bool) -> Option<Message> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .messages
+            .iter()
+            .rev()
+            .map(|(msg, _)| msg)
+            .find(|msg| msg.room == room && keep(msg))
+            .cloned()
+    }
+
+    // the last `n` buffered messages, in `room` if given, oldest first
+    pub fn last(&self, room: Option<&str>, n: usize) -> Vec<Message> {
+        let inner = self.inner.lock().unwrap();
+        let mut messages: Vec<_> = inner
+            .messages
+            .iter()
+            .rev()
+            .map(|(msg, _)| msg)
+            .filter(|msg| room.is_none_or(|room| msg.room == room))
+            .take(n)
+            .cloned()
+            .collect();
+        messages.reverse();
+        messages
+    }
+}
+
+// how long `message` is as json, its own `byte_len` included. filling that
+// in can make the message a digit longer, so it's measured until it settles
+fn serialized_len(message: &mut Message) -> usize {
+    let mut len = 0;
+    loop {
+        message.byte_len = Some(len);
+        match json::to_string(message).map_or(0, |json| json.len()) {
+            measured if measured == len => return len,
+            measured => len = measured,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::tokio::sync::broadcast::channel;
+
+    use super::*;
+
+    fn publish(history: &History, queue: &Sender<Message>, room: &str, username: &str) -> Message {
+        let mut message = Message::new(room.into(), username.into(), "hi".into());
+        history.publish(queue, &mut message);
+        message
+    }
+
+    fn with_message(history: &History, queue: &Sender<Message>, text: &str) -> u64 {
+        let mut message = Message::new("a".into(), "ann".into(), text.into());
+        history.publish(queue, &mut message);
+        message.id
+    }
+
+    fn kept(history: &History) -> Vec<u64> {
+        history.since(None, 0).iter().map(|msg| msg.id).collect()
+    }
+
+    #[test]
+    fn the_buffer_is_bounded_by_count() {
+        let config = Config {
+            history_size: 2,
+            ..Config::default()
+        };
+        let history = History::new(&config);
+        let queue = channel(16).0;
+        for _ in 0..3 {
+            with_message(&history, &queue, "hi");
+        }
+        assert_eq!(kept(&history), [2, 3]);
+    }
+
+    #[test]
+    fn the_buffer_is_bounded_by_bytes() {
+        let config = Config {
+            history_bytes: 800,
+            ..Config::default()
+        };
+        let history = History::new(&config);
+        let queue = channel(16).0;
+        let mut rx = queue.subscribe();
+
+        let text = "x".repeat(200);
+        for _ in 0..3 {
+            with_message(&history, &queue, &text);
+        }
+        // each is a few hundred bytes once serialized, so two fit
+        assert_eq!(kept(&history), [2, 3]);
+
+        // too big to keep at all, but still sent, and nothing is evicted
+        let huge = with_message(&history, &queue, &"x".repeat(1000));
+        assert_eq!(kept(&history), [2, 3]);
+        let sent: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|msg| msg.id)
+            .collect();
+        assert_eq!(sent.last(), Some(&huge));
+    }
+
+    #[test]
+    fn timestamps_strictly_increase_within_a_room() {
+        let history = History::new(&Config::default());
+        let queue = channel(16).0;
+
+        // far more than fit in a millisecond
+        let stamps: Vec<_> = (0..200)
+            .map(|_| publish(&history, &queue, "a", "ann").timestamp)
+            .collect();
+        assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn rooms_are_forgotten_once_their_messages_are_gone() {
+        let config = Config {
+            history_size: 2,
+            ..Config::default()
+        };
+        let history = History::new(&config);
+        let queue = channel(64).0;
+        for n in 0..50 {
+            publish(&history, &queue, &format!("made-up-{}", n), "ann");
+        }
+        assert_eq!(history.inner.lock().unwrap().rooms.len(), 2);
+
+        let mut reply = Message::new("elsewhere".into(), "bob".into(), "away".into());
+        history.deliver(&queue, &mut reply);
+        assert!(!history
+            .inner
+            .lock()
+            .unwrap()
+            .rooms
+            .contains_key("elsewhere"));
+    }
+
+    #[test]
+    fn forgotten_rooms_still_only_go_forward() {
+        let config = Config {
+            history_size: 1,
+            ..Config::default()
+        };
+        let history = History::new(&config);
+        let queue = channel(512).0;
+
+        // a burst bumps "a" ahead of the clock before it's forgotten
+        let mut stamps: Vec<_> = (0..200)
+            .map(|_| publish(&history, &queue, "a", "ann").timestamp)
+            .collect();
+        publish(&history, &queue, "b", "ann");
+        assert!(!history.inner.lock().unwrap().rooms.contains_key("a"));
+        stamps.push(publish(&history, &queue, "a", "ann").timestamp);
+        assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn runs_survive_their_earlier_messages_being_evicted() {
+        let config = Config {
+            history_size: 1,
+            ..Config::default()
+        };
+        let history = History::new(&config);
+        let queue = channel(16).0;
+        publish(&history, &queue, "a", "ann");
+        assert!(publish(&history, &queue, "a", "ann").continuation);
+    }
+
+    #[test]
+    fn ranges_are_inclusive_oldest_first_and_say_if_theres_more() {
+        let history = History::new(&Config::default());
+        let queue = channel(16).0;
+        let stamps: Vec<_> = (0..5)
+            .map(|n| publish(&history, &queue, if n == 2 { "b" } else { "a" }, "ann").timestamp)
+            .collect();
+        let ids = |messages: Vec<Message>| messages.iter().map(|msg| msg.id).collect::<Vec<_>>();
+
+        let (all, more) = history.range("a", (stamps[0], stamps[4]), 10, |_| true);
+        assert_eq!((ids(all), more), (vec![1, 2, 4, 5], false));
+        let (first, more) = history.range("a", (stamps[0], stamps[4]), 2, |_| true);
+        assert_eq!((ids(first), more), (vec![1, 2], true));
+        let (exact, more) = history.range("a", (stamps[1], stamps[1]), 2, |_| true);
+        assert_eq!((ids(exact), more), (vec![2], false));
+        let (kept, _) = history.range("a", (stamps[0], stamps[4]), 10, |msg| msg.id != 4);
+        assert_eq!(ids(kept), [1, 2, 5]);
+        let (none, more) = history.range("a", (stamps[4] + 1, u64::MAX), 10, |_| true);
+        assert_eq!((none.len(), more), (0, false));
+    }
+
+    #[test]
+    fn byte_lengths_are_the_serialized_length() {
+        let config = Config {
+            byte_lengths: true,
+            ..Config::default()
+        };
+        let history = History::new(&config);
+        let queue = channel(16).0;
+        let mut message = Message::new("a".into(), "ann".into(), "héllo 👋 日本".into());
+        history.publish(&queue, &mut message);
+        let json = json::to_string(&message).unwrap();
+        assert_eq!(message.byte_len, Some(json.len()));
+
+        let history = History::new(&Config::default());
+        assert_eq!(publish(&history, &queue, "a", "ann").byte_len, None);
+    }
+
+    #[test]
+    fn the_fence_splits_replay_from_live() {
+        let history = History::new(&Config::default());
+        let queue = channel(16).0;
+        publish(&history, &queue, "a", "ann");
+        publish(&history, &queue, "a", "ann");
+
+        let (mut rx, fence) = history.subscribe(&queue);
+        assert_eq!(fence, 2);
+        publish(&history, &queue, "a", "ann");
+
+        let replayed: Vec<_> = history
+            .since(None, 0)
+            .into_iter()
+            .filter(|msg| msg.id <= fence)
+            .collect();
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(rx.try_recv().unwrap().id, 3);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn continuations_follow_the_room_order() {
+        let history = History::new(&Config::default());
+        let queue = channel(16).0;
+
+        assert!(!publish(&history, &queue, "a", "ann").continuation);
+        assert!(publish(&history, &queue, "a", "ann").continuation);
+        // other rooms don't break a run
+        assert!(!publish(&history, &queue, "b", "ben").continuation);
+        assert!(publish(&history, &queue, "a", "ann").continuation);
+        assert!(!publish(&history, &queue, "a", "ben").continuation);
+    }
+
+    #[test]
+    fn every_published_message_counts_towards_runs() {
+        let history = History::new(&Config::default());
+        let queue = channel(16).0;
+
+        let original = publish(&history, &queue, "a", "ann");
+        publish(&history, &queue, "b", "ann");
+
+        // a cross-post never continues a run, but it's who spoke last
+        let mut copy = original.clone();
+        copy.room = "b".into();
+        copy.crossposted_from = Some(crate::Origin {
+            room: "a".into(),
+            id: original.id,
+        });
+        history.publish(&queue, &mut copy);
+        assert!(!copy.continuation);
+
+        // an announcement breaks ann's run
+        publish(&history, &queue, "a", "announcements");
+        assert!(!publish(&history, &queue, "a", "ann").continuation);
+    }
+
+    #[test]
+    fn delivered_messages_leave_runs_alone() {
+        let history = History::new(&Config::default());
+        let queue = channel(16).0;
+
+        publish(&history, &queue, "a", "ann");
+        let mut reply = Message::new("a".into(), "ben".into(), "away".into());
+        history.deliver(&queue, &mut reply);
+        assert!(!reply.continuation);
+        assert!(publish(&history, &queue, "a", "ann").continuation);
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::Config;
+
+// counts concurrent holders per key (an ip, a room, ...) so long-lived
+// requests can be capped. a slot is given back when its `Slot` is dropped,
+// however the request ends
+pub struct Slots<K>(Mutex<HashMap<K, usize>>);
+
+impl<K> Default for Slots<K> {
+    fn default() -> Self {
+        Slots(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<K: Hash + Eq + Clone> Slots<K> {
+    // take one of `key`'s `limit` slots, or `None` if they're all in use. a
+    // limit of 0 means unlimited
+    pub fn acquire(&self, key: K, limit: usize) -> Option<Slot<'_, K>> {
+        let mut held = self.0.lock().unwrap();
+        let count = held.entry(key.clone()).or_default();
+        if limit > 0 && *count >= limit {
+            return None;
+        }
+
+        *count += 1;
+        Some(Slot { slots: self, key })
+    }
+}
+
+pub struct Slot<'a, K: Hash + Eq> {
+    slots: &'a Slots<K>,
+    key: K,
+}
+
+impl<K: Hash + Eq> Drop for Slot<'_, K> {
+    fn drop(&mut self) {
+        let mut held = self.slots.0.lock().unwrap();
+        if let Some(count) = held.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                held.remove(&self.key);
+            }
+        }
+    }
+}
+
+// counts how often each key has done something within a fixed window, for
+// capping cheap requests that shouldn't be sent in floods
+pub struct Rates<K>(Mutex<HashMap<K, (Instant, usize)>>);
+
+impl<K> Default for Rates<K> {
+    fn default() -> Self {
+        Rates(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<K: Hash + Eq> Rates<K> {
+    // count one more for `key`, unless it's already had `limit` in the
+    // current `window`. a limit of 0 means unlimited
+    pub fn allow(&self, key: K, limit: usize, window: Duration) -> bool {
+        if limit == 0 {
+            return true;
+        }
+
+        let mut counts = self.0.lock().unwrap();
+        let now = Instant::now();
+        counts.retain(|_, (start, _)| now.duration_since(*start) < window);
+
+        let (_, count) = counts.entry(key).or_insert((now, 0));
+        if *count >= limit {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+// a cap on per-user state kept for names without an account. anyone can
+// pick any name, so without one cycling through names would fill the server
+// up. account holders' state is always kept
+pub struct Names {
+    accounts: HashSet<String>,
+    // entries kept for names without an account; 0 for no limit
+    limit: usize,
+}
+
+impl Names {
+    pub fn new(config: &Config) -> Self {
+        Names {
+            accounts: config
+                .accounts
+                .values()
+                .map(|account| account.username.clone())
+                .collect(),
+            limit: config.max_anonymous_users,
+        }
+    }
+
+    // about to give `username` an entry in `entries`: if it's new, has no
+    // account, and `limit` others without one are already there, drop the
+    // one least recently `touched`
+    pub fn make_room<V>(
+        &self,
+        entries: &mut HashMap<String, V>,
+        username: &str,
+        touched: impl Fn(&V) -> Instant,
+    ) {
+        if self.limit == 0 || entries.contains_key(username) || self.accounts.contains(username) {
+            return;
+        }
+
+        let anonymous = || {
+            entries
+                .iter()
+                .filter(|(name, _)| !self.accounts.contains(*name))
+        };
+        if anonymous().count() < self.limit {
+            return;
+        }
+        let oldest = anonymous()
+            .min_by_key(|(_, entry)| touched(entry))
+            .map(|(name, _)| name.clone());
+        if let Some(oldest) = oldest {
+            entries.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_given_back_when_dropped() {
+        let slots = Slots::default();
+        let first = slots.acquire("ip", 2).unwrap();
+        let _second = slots.acquire("ip", 2).unwrap();
+        assert!(slots.acquire("ip", 2).is_none());
+        assert!(slots.acquire("other", 2).is_some());
+
+        drop(first);
+        assert!(slots.acquire("ip", 2).is_some());
+        // 0 for no limit
+        assert!(slots.acquire("ip", 0).is_some());
+    }
+}
@@ -2,41 +2,1098 @@
 #[macro_use]
 extern crate rocket;
 
+mod announcements;
+mod anonymize;
+mod auth;
+mod away;
+mod config;
+mod cursors;
+mod drafts;
+mod forms;
+mod geo;
+mod hash;
+mod heat;
+mod history;
+mod limits;
+mod metrics;
+mod mutes;
+mod names;
+mod presence;
+mod receipts;
+mod reports;
+mod stats;
+mod templates;
+mod text;
+
+use std::{
+    net::IpAddr,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use auth::{Admin, Moderator, Role, User};
+use away::Away;
+use config::{Config, Feature};
+use cursors::Cursors;
+use drafts::{Draft, Drafts};
+use forms::ChatForm;
+use geo::{Geo, GeoResolver, NoGeo};
+use heat::Cooldowns;
+use history::History;
+use limits::{Rates, Slots};
+use metrics::Metrics;
+use mutes::Mutes;
+use presence::Presence;
+use receipts::Receipts;
+use reports::{Report, Reports};
 use rocket::{
-    form::Form,
-    fs::relative,
-    fs::FileServer,
+    fairing::AdHoc,
+    fs::{FileServer, Options},
+    http::{CookieJar, Status},
     response::stream::{Event, EventStream},
-    serde::{Deserialize, Serialize},
+    serde::{
+        json::{json, Json, Value},
+        Serialize,
+    },
     tokio::select,
-    tokio::sync::broadcast::{channel, error::RecvError, Sender},
-    Shutdown, State,
+    tokio::sync::broadcast::{channel, error::RecvError, Receiver, Sender},
+    tokio::time,
+    Build, Rocket, Shutdown, State,
 };
+use stats::UserStats;
 
-#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
-#[serde(crate = "rocket::serde")]
+// what a client posts to /message
+#[derive(Debug, FromForm)]
+struct NewMessage {
+    #[field(validate = len(..30))]
+    pub room: String,
+    #[field(validate = len(..20))]
+    pub username: String,
+    pub message: String,
+    // the sending device's session, so replies meant only for the sender
+    // reach that device alone
+    #[field(validate = len(..64))]
+    pub session: Option<String>,
+}
 
+// a message as the server broadcasts and remembers it. everything beyond
+// room/username/message is filled in by the server
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
 struct Message {
+    // increasing across all rooms, so clients can tell what they've seen
+    pub id: u64,
+    // milliseconds since the unix epoch, by the server's clock. strictly
+    // increasing within a room
+    pub timestamp: u64,
+    pub room: String,
+    pub username: String,
+    pub message: String,
+    // a stable fingerprint of room, username, message and timestamp, for
+    // clients to dedupe with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    // how many bytes this whole message takes as json, this field included,
+    // for clients sizing a message before they render it. lite streams leave
+    // it out along with the other extras
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_len: Option<usize>,
+    // true when the previous message in the room came from the same user
+    // within the configured window
+    pub continuation: bool,
+    // coarse origin of the poster, for moderators only. never broadcast
+    #[serde(skip)]
+    pub geo: Option<Geo>,
+    // who actually posted when `username` is a demo-mode pseudonym. stays on
+    // the server like `geo`
+    #[serde(skip)]
+    pub real_username: Option<String>,
+    // when the post was accepted, for timing how long delivery takes
+    #[serde(skip)]
+    pub sent_at: Option<Instant>,
+    // the sender's role, for clients to show a badge. it comes from the
+    // account the post was signed in as, never from the form
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    // where this message was copied from, if it's a cross-post
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crossposted_from: Option<Origin>,
+    // an internal /selfcheck probe, which no stream ever passes on
+    #[serde(skip)]
+    pub probe: bool,
+    // set when only this user should see the message, like an away
+    // auto-reply. it's never kept in the history
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    // narrows `to` down to one of the user's sessions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_session: Option<String>,
+}
+
+impl Message {
+    // a fresh message with none of the server's fields filled in yet
+    fn new(room: String, username: String, message: String) -> Self {
+        Message {
+            id: 0,
+            timestamp: 0,
+            room,
+            username,
+            message,
+            content_hash: None,
+            byte_len: None,
+            continuation: false,
+            geo: None,
+            real_username: None,
+            sent_at: None,
+            role: None,
+            crossposted_from: None,
+            probe: false,
+            to: None,
+            to_session: None,
+        }
+    }
+
+    // the event a stream sends this message as, in the shape its `mode`
+    // asks for
+    fn event(&self, mode: Mode) -> Event {
+        match mode {
+            Mode::Full => Event::json(self),
+            Mode::Lite => Event::json(&LiteMessage {
+                id: self.id,
+                timestamp: self.timestamp,
+                room: &self.room,
+                username: &self.username,
+                message: &self.message,
+                continuation: self.continuation,
+                to: self.to.as_deref(),
+                to_session: self.to_session.as_deref(),
+            }),
+        }
+    }
+}
+
+// how much of each message an /events stream is sent. lite leaves out the
+// optional extras (content hash, byte length, role badge, cross-post origin)
+// for clients on slow connections
+#[derive(Debug, Clone, Copy, Default, FromFormField)]
+enum Mode {
+    #[default]
+    Full,
+    Lite,
+}
+
+// what lite mode sends of a message: enough to show it, and who it's for
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct LiteMessage<'a> {
+    id: u64,
+    timestamp: u64,
+    room: &'a str,
+    username: &'a str,
+    message: &'a str,
+    continuation: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_session: Option<&'a str>,
+}
+
+// the room and id of the message a cross-post was copied from
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Origin {
+    pub room: String,
+    pub id: u64,
+}
+
+// a message as moderators see it, including the fields kept off the wire
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ModMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    pub geo: Option<Geo>,
+    pub real_username: Option<String>,
+}
+
+impl From<Message> for ModMessage {
+    fn from(message: Message) -> Self {
+        ModMessage {
+            geo: message.geo.clone(),
+            real_username: message.real_username.clone(),
+            message,
+        }
+    }
+}
+
+// a page of /history-range, and whether it stopped short of the range's end
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Range {
+    pub messages: Vec<Message>,
+    pub has_more: bool,
+}
+
+// what a long-poll hands back: anything new, plus the `since` to pass next
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Poll {
+    pub messages: Vec<Message>,
+    pub cursor: u64,
+}
+
+// the gist of a room's latest message, for a room list that isn't subscribed
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Preview {
+    pub room: String,
+    pub id: u64,
+    pub timestamp: u64,
+    pub username: String,
+    // cut short past the configured length
+    pub message: String,
+}
+
+// an away note for `username`; empty to clear it
+#[derive(Debug, FromForm)]
+struct AwayNote {
+    #[field(validate = len(..20))]
+    pub username: String,
+    #[field(validate = len(..200))]
+    pub message: String,
+}
+
+// advance warning of planned downtime, sent to every open stream
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Notice {
+    pub message: String,
+    // estimated seconds of downtime, and when we expect to be back in
+    // milliseconds since the unix epoch
+    pub downtime: u64,
+    pub back_at: u64,
+}
+
+// an admin announcing maintenance: what's happening and for how long
+#[derive(Debug, FromForm)]
+struct Maintenance {
+    #[field(validate = len(..200))]
+    pub message: String,
+    pub downtime: u64,
+}
+
+// a moderator asking for message `id` to be copied into `room`
+#[derive(Debug, FromForm)]
+struct CrossPost {
+    pub id: u64,
     #[field(validate = len(..30))]
     pub room: String,
+}
+
+// a client telling us it has seen everything in `room` up to `id`. with a
+// `username` it's also a read receipt for the room
+#[derive(Debug, FromForm)]
+struct Read {
+    #[field(validate = len(..30))]
+    pub room: String,
+    pub id: u64,
+    #[field(validate = len(..20))]
+    pub username: Option<String>,
+}
+
+// whether `username` sends read receipts
+#[derive(Debug, FromForm)]
+struct ReceiptSetting {
     #[field(validate = len(..20))]
     pub username: String,
+    pub enabled: bool,
+}
+
+// `username` muting (or unmuting) `target`
+#[derive(Debug, FromForm)]
+struct Mute {
+    #[field(validate = len(..20))]
+    pub username: String,
+    #[field(validate = len(..20))]
+    pub target: String,
+}
+
+// a signed-in user's unsent message in `room`; empty to discard it
+#[derive(Debug, FromForm)]
+struct SaveDraft {
+    #[field(validate = len(..30))]
+    pub room: String,
+    #[field(validate = len(..2000))]
     pub message: String,
 }
 
+// a client that noticed ids `from` through `to` never arrived in `room`
+#[derive(Debug, FromForm)]
+struct Gap {
+    #[field(validate = len(..30))]
+    pub room: String,
+    pub from: u64,
+    pub to: u64,
+}
+
+// a user reporting message `id`, optionally saying why
+#[derive(Debug, FromForm)]
+struct NewReport {
+    pub id: u64,
+    #[field(validate = len(..200))]
+    pub reason: Option<String>,
+}
+
+// a moderator done reviewing the reports against message `id`
+#[derive(Debug, FromForm)]
+struct Dismiss {
+    pub id: u64,
+}
+
 // Post Messages Endpoint
+// 422 for a message with nothing visible in it, one that's too long or
+// multi-line for the room, one that doesn't fit the room's template, or a
+// username that could pass for an account holder's. 429 for someone cooling
+// down after being flagged
 #[post("/message", data = "<form>")]
-fn post(form: Form<Message>, queue: &State<Sender<Message>>) {
-    // inside the fn we simply send the message to all receivers
-    // the send method returns a result type b/c sending a message could fail
-    // if there are no receivers. in this ex, we dont care about that case and will ignore
-    let _res = queue.send(form.into_inner());
+#[allow(clippy::too_many_arguments)]
+fn post(
+    form: ChatForm<NewMessage>,
+    user: Option<User>,
+    ip: Option<IpAddr>,
+    queue: &State<Sender<Message>>,
+    history: &State<History>,
+    away: &State<Away>,
+    drafts: &State<Drafts>,
+    cooldowns: &State<Cooldowns>,
+    geo: &State<Box<dyn GeoResolver>>,
+    config: &State<Config>,
+) -> Result<(), (Status, Value)> {
+    let mut form = form.into_inner();
+    let settings = config.room(&form.room);
+    if config.feature(&form.room, Feature::RequireVisible) && !text::visible(&form.message) {
+        return Err(forms::invalid(
+            "message",
+            "messages must have something visible in them",
+        ));
+    }
+    if let Some(max) = config.max_message_length(&form.room) {
+        if form.message.chars().count() > max {
+            let error = format!("messages in this room can be at most {} characters", max);
+            return Err(forms::invalid("message", error));
+        }
+    }
+    if settings.single_line && form.message.contains(['\n', '\r']) {
+        return Err(forms::invalid(
+            "message",
+            "messages in this room must be one line",
+        ));
+    }
+    if let Some(template) = &settings.template {
+        template
+            .check(&form.message, settings.template_hint.as_deref())
+            .map_err(|hint| forms::invalid("message", hint))?;
+    }
+
+    let cooldown = config.away_reply_cooldown();
+
+    // signed-in users post under their account's name, so the badge can't
+    // end up on someone else's words
+    let role = user
+        .as_ref()
+        .map_or(Role::Member, |User(account)| account.role);
+    form.username = acting_name(config, user.as_ref(), &form.username)?;
+
+    if let Some(base) = config.flag_cooldown() {
+        let (half_life, max) = (config.heat_half_life(), config.max_flag_cooldown());
+        if let Err(wait) = cooldowns.post(&form.username, half_life, base, max) {
+            let error = json!({
+                "error": "slow down: you posted something that was flagged",
+                "retry_after": wait.as_secs_f64().ceil() as u64,
+            });
+            return Err((Status::TooManyRequests, error));
+        }
+    }
+
+    // posting is what being back looks like
+    away.clear(&form.username);
+
+    // auto-replies from anyone mentioned who's away, for the sender only. in
+    // demo mode pseudonyms are all anyone sees, so they're what gets
+    // mentioned, and a real name mentioned there mustn't give anything away
+    let replies: Vec<_> = away::mentions(&form.message)
+        .filter_map(|name| match config.anonymize {
+            true => away.find(|away| anonymize::pseudonym(away) == name),
+            false => Some(name.to_string()),
+        })
+        .filter(|name| *name != form.username)
+        .filter_map(|name| {
+            let note = away.reply(&form.username, &name, cooldown)?;
+            let name = match config.anonymize {
+                true => anonymize::pseudonym(&name),
+                false => name,
+            };
+            let mut reply = Message::new(form.room.clone(), name, note);
+            reply.to = Some(form.username.clone());
+            reply.to_session = form.session.clone();
+            Some(reply)
+        })
+        .collect();
+
+    let mut message = Message::new(form.room, form.username, form.message);
+    if config.anonymize {
+        let pseudonym = anonymize::pseudonym(&message.username);
+        message.real_username = Some(std::mem::replace(&mut message.username, pseudonym));
+    }
+
+    message.geo = match ip {
+        Some(ip) if config.feature(&message.room, Feature::GeoHints) => geo.resolve(ip),
+        _ => None,
+    };
+    message.role = config
+        .feature(&message.room, Feature::RoleBadges)
+        .then_some(role);
+    // it's going out, so there's no draft left to restore
+    if user.is_some() {
+        let username = message.real_username.as_ref().unwrap_or(&message.username);
+        drafts.clear(username, &message.room);
+    }
+    // send the message to all receivers, keeping it for anyone catching up
+    history.publish(queue, &mut message);
+
+    for mut reply in replies {
+        history.deliver(queue, &mut reply);
+    }
+    Ok(())
+}
+
+// Cross-Post Endpoint: copy a recent message into another room, pointing back
+// at the original. moderators only, and 404 if the message isn't in the
+// history anymore
+#[post("/crosspost", data = "<form>")]
+fn crosspost(
+    form: ChatForm<CrossPost>,
+    moderator: Moderator,
+    queue: &State<Sender<Message>>,
+    history: &State<History>,
+) -> Option<Json<Message>> {
+    let original = history.get(form.id)?;
+    info!(
+        "{} cross-posted message {} from {} to {}",
+        moderator.0.username, original.id, original.room, form.room
+    );
+
+    let mut copy = original.clone();
+    copy.room = form.into_inner().room;
+    copy.crossposted_from = Some(Origin {
+        room: original.room,
+        id: original.id,
+    });
+    history.publish(queue, &mut copy);
+    Some(Json(copy))
+}
+
+// Maintenance Notice Endpoint: warn everyone connected ahead of a restart so
+// clients can show a banner and expect to reconnect. admins only
+#[post("/maintenance", data = "<form>")]
+fn maintenance(
+    form: ChatForm<Maintenance>,
+    admin: Admin,
+    notices: &State<Sender<Notice>>,
+) -> Json<Notice> {
+    let form = form.into_inner();
+    let notice = Notice {
+        message: form.message,
+        downtime: form.downtime,
+        back_at: now_millis().saturating_add(form.downtime.saturating_mul(1000)),
+    };
+
+    info!(
+        "{} announced maintenance: {}",
+        admin.0.username, notice.message
+    );
+    let _res = notices.send(notice.clone());
+    Json(notice)
+}
+
+// Away Endpoint: set an away note for `username`, or clear it with an empty
+// one. it's cleared anyway as soon as they post again. `username` goes by
+// the same rules as for /message, so a 422 if it could pass for an account
+// holder's
+#[post("/away", data = "<form>")]
+fn set_away(
+    form: ChatForm<AwayNote>,
+    user: Option<User>,
+    away: &State<Away>,
+    config: &State<Config>,
+) -> Result<(), (Status, Value)> {
+    let note = form.into_inner();
+    let username = acting_name(config, user.as_ref(), &note.username)?;
+    match note.message.is_empty() {
+        true => away.clear(&username),
+        false => away.set(&username, note.message),
+    }
+    Ok(())
+}
+
+// Mute Endpoint: stop sending `target`'s messages to the muter on every
+// stream they open, until they unmute them. the muter is resolved the way
+// /events resolves them. 422 once they've muted as many users as the config
+// allows
+#[post("/mute", data = "<form>")]
+fn mute(
+    form: ChatForm<Mute>,
+    user: Option<User>,
+    mutes: &State<Mutes>,
+    config: &State<Config>,
+) -> Result<(), (Status, Value)> {
+    let username = acting_name(config, user.as_ref(), &form.username)?;
+    match mutes.mute(&username, &form.target, config.max_mutes) {
+        true => Ok(()),
+        false => Err(forms::invalid("target", "too many users muted")),
+    }
+}
+
+#[post("/unmute", data = "<form>")]
+fn unmute(
+    form: ChatForm<Mute>,
+    user: Option<User>,
+    mutes: &State<Mutes>,
+    config: &State<Config>,
+) -> Result<(), (Status, Value)> {
+    let username = acting_name(config, user.as_ref(), &form.username)?;
+    mutes.unmute(&username, &form.target);
+    Ok(())
+}
+
+// Draft Endpoints: save what a signed-in user is typing in a room, so it can
+// be restored after a reload or on another device. the client is expected to
+// debounce saves. posting to the room throws the draft away
+#[post("/draft", data = "<form>")]
+fn save_draft(
+    form: ChatForm<SaveDraft>,
+    user: User,
+    drafts: &State<Drafts>,
+    config: &State<Config>,
+) {
+    let form = form.into_inner();
+    drafts.save(
+        &user.0.username,
+        &form.room,
+        form.message,
+        config.draft_ttl(),
+        config.max_drafts,
+    );
+}
+
+#[get("/draft?<room>")]
+fn get_draft(
+    room: &str,
+    user: User,
+    drafts: &State<Drafts>,
+    config: &State<Config>,
+) -> Option<Json<Draft>> {
+    drafts
+        .get(&user.0.username, room, config.draft_ttl())
+        .map(Json)
+}
+
+// Read Cursor Endpoint: also tells the room the user has read up to `id`
+// with a `read_receipt` event, unless they've turned receipts off
+#[post("/read", data = "<form>")]
+fn read(
+    form: ChatForm<Read>,
+    user: Option<User>,
+    receipts: &State<Receipts>,
+    config: &State<Config>,
+    cookies: &CookieJar<'_>,
+) {
+    let mut cursors = Cursors::from_cookies(cookies);
+    cursors.advance(&form.room, form.id);
+    cursors.save(cookies);
+
+    if !config.feature(&form.room, Feature::ReadReceipts) {
+        return;
+    }
+    if let Some(name) = form
+        .username
+        .as_deref()
+        .and_then(|name| shown_name(config, user, name))
+    {
+        receipts.read(&form.room, &name, form.id, config.receipt_interval());
+    }
+}
+
+// Read Receipt Setting Endpoint: turn `username`'s read receipts on or off
+#[post("/receipts", data = "<form>")]
+fn set_receipts(
+    form: ChatForm<ReceiptSetting>,
+    user: Option<User>,
+    receipts: &State<Receipts>,
+    config: &State<Config>,
+) {
+    if let Some(name) = shown_name(config, user, &form.username) {
+        receipts.set_enabled(&name, form.enabled);
+    }
+}
+
+// Report Endpoint: flag a message as abusive. moderators watching the room
+// are told straight away, and with enough reports the message is kept out of
+// replays until one of them dismisses the reports. reporting needs an account
+// so each user only counts once; 404 if the message isn't in the history
+// anymore and 409 if this user already reported it. a sender whose message
+// gets hidden has to wait longer between posts for a while
+#[post("/report", data = "<form>")]
+fn report(
+    form: ChatForm<NewReport>,
+    user: User,
+    history: &State<History>,
+    reports: &State<Reports>,
+    cooldowns: &State<Cooldowns>,
+    moderators: &State<Sender<Report>>,
+    config: &State<Config>,
+) -> Result<Json<Report>, Status> {
+    let form = form.into_inner();
+    let message = history.get(form.id).ok_or(Status::NotFound)?;
+    let threshold = config.report_hide_threshold;
+    let (count, hidden) = reports
+        .file(message.id, &user.0.username, threshold)
+        .ok_or(Status::Conflict)?;
+
+    // the report that hid it counts against the sender, just the once
+    if hidden && count == threshold {
+        let sender = message.real_username.as_ref().unwrap_or(&message.username);
+        cooldowns.flag(sender, config.heat_half_life());
+    }
+
+    let report = Report {
+        id: message.id,
+        room: message.room,
+        reporter: user.0.username,
+        reason: form.reason,
+        count,
+        hidden,
+    };
+    let _res = moderators.send(report.clone());
+    Ok(Json(report))
+}
+
+// Dismiss Reports Endpoint: a moderator has reviewed a reported message and
+// it can be shown again. 404 if it had no reports
+#[post("/report/dismiss", data = "<form>")]
+fn dismiss_reports(
+    form: ChatForm<Dismiss>,
+    moderator: Moderator,
+    reports: &State<Reports>,
+) -> Option<()> {
+    reports.dismiss(form.id).then(|| {
+        info!(
+            "{} dismissed the reports against message {}",
+            moderator.0.username, form.id
+        )
+    })
+}
+
+// Previews Endpoint: the latest message in each of `rooms` (given as
+// `?rooms=a&rooms=b`), truncated, for a sidebar that doesn't want a stream
+// per room. rooms with nothing in the history are left out, and so are
+// messages hidden by reports. 422 for more rooms than the config allows, or a
+// room name too long for /message to have posted to
+#[get("/previews?<rooms>")]
+fn previews(
+    rooms: Vec<String>,
+    history: &State<History>,
+    reports: &State<Reports>,
+    config: &State<Config>,
+) -> Result<Json<Vec<Preview>>, (Status, Value)> {
+    if config.max_preview_rooms > 0 && rooms.len() > config.max_preview_rooms {
+        let error = format!("at most {} rooms at a time", config.max_preview_rooms);
+        return Err(forms::invalid("rooms", error));
+    }
+    if rooms.iter().any(|room| room.len() >= 30) {
+        return Err(forms::invalid("rooms", "room names are under 30 bytes"));
+    }
+
+    let previews = rooms
+        .into_iter()
+        .filter_map(|room| history.latest(&room, |msg| !reports.is_hidden(msg.id)))
+        .map(|msg| Preview {
+            message: truncate(&msg.message, config.preview_length),
+            room: msg.room,
+            id: msg.id,
+            timestamp: msg.timestamp,
+            username: msg.username,
+        })
+        .collect();
+
+    Ok(Json(previews))
+}
+
+// `text` cut down to `max` characters, with an ellipsis if anything was cut
+fn truncate(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+// History Range Endpoint: messages in `room` timestamped between `from` and
+// `to` (milliseconds since the unix epoch, inclusive), oldest first, for
+// jumping to a date. a span wider than the config allows ends early, and at
+// most `limit` come back, capped by the config, with `has_more` set if either
+// cut anything off; ask again from just after the last one for the rest. 422
+// if `to` is before `from`. only what's still in the history can be found
+#[get("/history-range?<room>&<from>&<to>&<limit>")]
+fn history_range(
+    room: &str,
+    from: u64,
+    to: u64,
+    limit: Option<usize>,
+    history: &State<History>,
+    reports: &State<Reports>,
+    config: &State<Config>,
+) -> Result<Json<Range>, (Status, Value)> {
+    if from > to {
+        return Err(forms::invalid("to", "the range has to end after it starts"));
+    }
+    let ends = match config.max_range_span.saturating_mul(1000) {
+        0 => to,
+        max_span => to.min(from.saturating_add(max_span)),
+    };
+    let max = match config.max_range_results {
+        0 => usize::MAX,
+        max => max,
+    };
+
+    let shown = |msg: &Message| !reports.is_hidden(msg.id);
+    let limit = limit.unwrap_or(max).min(max);
+    let (messages, mut has_more) = history.range(room, (from, ends), limit, shown);
+    if !has_more && ends < to {
+        (_, has_more) = history.range(room, (ends + 1, to), 0, shown);
+    }
+
+    Ok(Json(Range { messages, has_more }))
+}
+
+// User Stats Endpoint: how much `username` has posted, where and when. only
+// for that user themselves, signed in, or a moderator; 404 if they've never
+// posted
+#[get("/user-stats?<username>")]
+fn user_stats(
+    username: &str,
+    user: User,
+    history: &State<History>,
+) -> Result<Json<UserStats>, Status> {
+    let User(account) = user;
+    if account.username != username && account.role < Role::Moderator {
+        return Err(Status::Forbidden);
+    }
+
+    history
+        .user_stats(username)
+        .map(Json)
+        .ok_or(Status::NotFound)
+}
+
+// Moderator History Endpoint: recent messages in `room` with the origin hints
+// that are kept out of the public stream
+#[get("/mod/history?<room>")]
+fn mod_history(
+    room: &str,
+    moderator: Moderator,
+    history: &State<History>,
+) -> Json<Vec<ModMessage>> {
+    // origin hints are sensitive, so leave a trail of who looked
+    info!(
+        "{} viewed moderator history for {}",
+        moderator.0.username, room
+    );
+
+    let messages = history
+        .since(Some(room), 0)
+        .into_iter()
+        .map(ModMessage::from)
+        .collect();
+
+    Json(messages)
 }
 
 // Receive Messages Endpoint
-#[get("/events")]
-async fn events(queue: &State<Sender<Message>>, mut end: Shutdown) -> EventStream![] {
+// the chat as it happens, after a `hello` event with the limits a composer
+// should enforce. membership, receipts, room stats and the reauth notice
+// come along as their own events, as the config says
+#[get("/events?<room>&<backfill>&<username>&<session>&<mode>")]
+#[allow(clippy::too_many_arguments)]
+async fn events<'r>(
+    // just this room. one with a user cap turns new users away with a 429
+    // once full, and streams without a username with a 422
+    room: Option<String>,
+    // how many recent messages to send before the live ones
+    backfill: Option<usize>,
+    // picks up messages meant only for this user, like away replies, leaves
+    // out anyone they've muted, and with a `room` counts them as present
+    username: Option<String>,
+    // an id the client picks per device, for messages meant for just one
+    session: Option<String>,
+    // `lite` trims messages down to what it takes to show them
+    mode: Option<Mode>,
+    user: Option<User>,
+    // sent `report` events too, and let into full rooms
+    moderator: Option<Moderator>,
+    queue: &State<Sender<Message>>,
+    notices: &State<Sender<Notice>>,
+    moderators: &State<Sender<Report>>,
+    history: &State<History>,
+    reports: &State<Reports>,
+    mutes: &'r State<Mutes>,
+    presence: &'r State<Presence>,
+    receipts: &State<Receipts>,
+    metrics: &'r State<Metrics>,
+    config: &'r State<Config>,
+    cookies: &CookieJar<'_>,
+    mut end: Shutdown,
+) -> Result<EventStream![Event + 'r], (Status, &'static str)> {
+    // only a token can go stale, so only signed-in streams run out
+    let signed_in = user.is_some();
+
+    let username = listening_name(config, user, username)?;
+
+    // subscribe before looking at the history so nothing posted in between
+    // slips through the gap. the fence says where the replay stops and the
+    // live stream starts, so nothing turns up in both or goes missing
+    let (mut rx, fence) = history.subscribe(queue);
+    let mut changes = presence.subscribe();
+    let mut read_by = receipts.subscribe();
+    let mut notices = notices.subscribe();
+    let mut flagged = moderators.subscribe();
+    let is_moderator = moderator.is_some();
+
+    // subscribed to changes first, so the new member sees their own join
+    let member = match (&room, &username) {
+        (Some(room), Some(username)) => {
+            let name = match config.anonymize {
+                true => anonymize::pseudonym(username),
+                false => username.clone(),
+            };
+            let limit = match moderator {
+                Some(_) => 0,
+                None => config.room(room).max_users,
+            };
+            let member = presence
+                .join(room, &name, limit)
+                .ok_or((Status::TooManyRequests, "room full"))?;
+            Some(member)
+        }
+        // a stream that doesn't say whose it is can't be counted
+        (Some(room), None) if moderator.is_none() && config.room(room).max_users > 0 => {
+            return Err((Status::UnprocessableEntity, "this room needs a username"));
+        }
+        _ => None,
+    };
+    let mut replay = Vec::new();
+
+    let cursors = Cursors::from_cookies(cookies);
+    for (cursor_room, id) in cursors.iter() {
+        if room.as_deref().is_none_or(|room| room == cursor_room)
+            && config.feature(cursor_room, Feature::Resume)
+        {
+            replay.extend(history.since(Some(cursor_room), id));
+        }
+    }
+    // hand the cookie back so it stays fresh for the next visit
+    cursors.save(cookies);
+
+    let backfill = backfill.unwrap_or(0).min(config.max_backfill);
+    if backfill > 0 {
+        replay.extend(history.last(room.as_deref(), backfill));
+    }
+
+    // the cursors and the backfill can turn up the same messages
+    replay.sort_by_key(|msg| msg.id);
+    replay.dedup_by_key(|msg| msg.id);
+    replay.retain(|msg| {
+        msg.id <= fence && !reports.is_hidden(msg.id) && !muted(mutes, username.as_deref(), msg)
+    });
+
+    let metrics = metrics.inner();
+    let slo = config.latency_slo();
+    let capacity = config.channel_capacity;
+    let threshold = config.slow_consumer_threshold;
+    let batch = config.replay_batch;
+    let mode = mode.unwrap_or_default();
+    let mut warned = false;
+
+    // the first tick waits a whole interval; joins have already said who's in
+    let every = config.room_stats_interval();
+    let period = every.unwrap_or(Duration::from_secs(3600));
+    let mut stats = time::interval_at(time::Instant::now() + period, period);
+
+    let lifetime = config.max_stream_lifetime().filter(|_| signed_in);
+    let mut expired = Box::pin(time::sleep(lifetime.unwrap_or(Duration::MAX)));
+
+    let hello = json!({
+        "room": room,
+        "max_message_length": config.max_message_length(room.as_deref().unwrap_or_default()),
+    });
+
+    Ok(EventStream! {
+        // leaves the room when the stream is dropped
+        let _member = member;
+
+        yield Event::json(&hello).event("hello");
+
+        for (i, msg) in replay.into_iter().enumerate() {
+            if batch > 0 && i > 0 && i % batch == 0 {
+                rocket::tokio::task::yield_now().await;
+            }
+            yield msg.event(mode);
+        }
+
+        loop {
+            let msg = select! {
+                msg = rx.recv() => match msg {
+                    Ok(msg) => msg,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                change = changes.recv() => {
+                    if let Ok(change) = change {
+                        if config.feature(&change.room, Feature::MembershipEvents)
+                            && room.as_ref().is_none_or(|room| *room == change.room)
+                        {
+                            yield Event::json(&change).event("membership");
+                        }
+                    }
+                    continue;
+                },
+                receipt = read_by.recv() => {
+                    if let Ok(receipt) = receipt {
+                        if room.as_ref().is_none_or(|room| *room == receipt.room) {
+                            yield Event::json(&receipt).event("read_receipt");
+                        }
+                    }
+                    continue;
+                },
+                notice = notices.recv() => {
+                    if let Ok(notice) = notice {
+                        yield Event::json(&notice).event("maintenance_notice");
+                    }
+                    continue;
+                },
+                _ = stats.tick(), if every.is_some() => {
+                    if let Some(room) = &room {
+                        let count = presence.count(room);
+                        if count > 0 {
+                            yield Event::json(&json!({ "room": room, "count": count }))
+                                .event("room_stats");
+                        }
+                    }
+                    continue;
+                },
+                _ = &mut expired => {
+                    yield reauth_required(lifetime);
+                    break;
+                },
+                report = flagged.recv(), if is_moderator => {
+                    if let Ok(report) = report {
+                        if room.as_ref().is_none_or(|room| *room == report.room) {
+                            yield Event::json(&report).event("report");
+                        }
+                    }
+                    continue;
+                },
+                _ = &mut end => break,
+            };
+
+            // warn once as the backlog nears the point where messages start
+            // getting dropped, and again if it recovers and creeps back up
+            let behind = rx.len();
+            if is_slow_consumer(behind, capacity, threshold) {
+                if !warned {
+                    warned = true;
+                    yield Event::json(&json!({ "behind": behind, "capacity": capacity }))
+                        .event("slow_consumer");
+                }
+            } else {
+                warned = false;
+            }
+
+            if !wanted(&msg, room.as_deref(), username.as_deref(), session.as_deref()) {
+                continue;
+            }
+            if msg.id <= fence || muted(mutes, username.as_deref(), &msg) {
+                continue;
+            }
+            let (id, sent_at) = (msg.id, msg.sent_at);
+            yield msg.event(mode);
+
+            // by the time we're resumed the event has been handed off to the
+            // connection, which is as close to delivered as we can see
+            if let (Some(slo), Some(sent_at)) = (slo, sent_at) {
+                metrics.record_latency(id, sent_at.elapsed(), slo);
+            }
+        }
+    })
+}
+
+// Presence Stream Endpoint: joins and leaves without the chat messages, for
+// something like a member list that doesn't need the rest. with a `room` it
+// starts with a `members` event listing who's already there, and only
+// follows that room. with `membership_events` off, this is the only place
+// they're sent
+#[get("/presence-stream?<room>")]
+fn presence_stream(
+    room: Option<String>,
+    presence: &State<Presence>,
+    mut end: Shutdown,
+) -> EventStream![] {
+    let mut changes = presence.subscribe();
+    let members = room.as_ref().map(|room| presence.members(room));
+
+    EventStream! {
+        if let (Some(room), Some(members)) = (&room, members) {
+            yield Event::json(&json!({ "room": room, "members": members })).event("members");
+        }
+
+        loop {
+            let change = select! {
+                change = changes.recv() => match change {
+                    Ok(change) => change,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut end => break,
+            };
+
+            if room.as_ref().is_none_or(|room| *room == change.room) {
+                yield Event::json(&change).event("membership");
+            }
+        }
+    }
+}
+
+// Firehose Endpoint: every message in every room, including ones meant for a
+// single user, with the moderator-only fields. for ops dashboards, so admins
+// only. past the configured rate, messages are skipped and the count of what
+// was skipped is reported once the next second starts. like any signed-in
+// /events, it ends with `reauth_required` after the configured lifetime
+#[get("/firehose")]
+async fn firehose(
+    admin: Admin,
+    queue: &State<Sender<Message>>,
+    config: &State<Config>,
+    mut end: Shutdown,
+) -> EventStream![] {
+    info!("{} opened the firehose", admin.0.username);
+
+    let lifetime = config.max_stream_lifetime();
+    let mut expired = Box::pin(time::sleep(lifetime.unwrap_or(Duration::MAX)));
+
     let mut rx = queue.subscribe();
+    let limit = config.firehose_rate_limit;
+    let mut window = Instant::now();
+    let (mut sent, mut skipped) = (0, 0);
 
     EventStream! {
         loop {
@@ -44,23 +1101,472 @@ async fn events(queue: &State<Sender<Message>>, mut end: Shutdown) -> EventStrea
                 msg = rx.recv() => match msg {
                     Ok(msg) => msg,
                     Err(RecvError::Closed) => break,
-                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Lagged(n)) => {
+                        skipped += n;
+                        continue;
+                    }
+                },
+                _ = &mut expired => {
+                    yield reauth_required(lifetime);
+                    break;
                 },
                 _ = &mut end => break,
             };
-            yield Event::json(&msg);
+
+            if msg.probe {
+                continue;
+            }
+
+            if window.elapsed() >= Duration::from_secs(1) {
+                window = Instant::now();
+                sent = 0;
+                if skipped > 0 {
+                    yield Event::json(&json!({ "skipped": skipped })).event("skipped");
+                    skipped = 0;
+                }
+            }
+
+            if limit > 0 && sent >= limit {
+                skipped += 1;
+                continue;
+            }
+
+            sent += 1;
+            yield Event::json(&ModMessage::from(msg));
+        }
+    }
+}
+
+// Long-Poll Endpoint: for clients that can't use server-sent events. returns
+// straight away if anything newer than `since` is buffered, otherwise waits up
+// to `timeout` seconds for the next message and returns it, or nothing.
+// `username` and `session` work as they do for /events, 422 included.
+// each ip only gets so many polls waiting at once; the rest get a 429
+#[get("/poll?<room>&<username>&<session>&<since>&<timeout>")]
+#[allow(clippy::too_many_arguments)]
+async fn poll(
+    room: Option<String>,
+    username: Option<String>,
+    session: Option<String>,
+    since: Option<u64>,
+    timeout: Option<u64>,
+    user: Option<User>,
+    ip: Option<IpAddr>,
+    queue: &State<Sender<Message>>,
+    history: &State<History>,
+    reports: &State<Reports>,
+    mutes: &State<Mutes>,
+    waiters: &State<Slots<IpAddr>>,
+    config: &State<Config>,
+    mut end: Shutdown,
+) -> Result<Json<Poll>, (Status, &'static str)> {
+    let username = listening_name(config, user, username)?;
+
+    // held until we return, whether that's with messages, on timeout, or
+    // because the client went away and the request was dropped
+    let _slot = match ip {
+        Some(ip) => Some(
+            waiters
+                .acquire(ip, config.max_polls_per_ip)
+                .ok_or((Status::TooManyRequests, "too many polls waiting"))?,
+        ),
+        None => None,
+    };
+
+    let since = since.unwrap_or(0);
+    let wait = timeout
+        .unwrap_or(config.max_poll_timeout)
+        .min(config.max_poll_timeout);
+
+    // same ordering as /events: subscribe first so nothing posted while we
+    // check the history is missed, and split the two at the fence
+    let (mut rx, fence) = history.subscribe(queue);
+    let mut messages = history.since(room.as_deref(), since);
+    messages.retain(|msg| {
+        msg.id <= fence && !reports.is_hidden(msg.id) && !muted(mutes, username.as_deref(), msg)
+    });
+    let live = since.max(fence);
+
+    if messages.is_empty() {
+        let next = async {
+            loop {
+                match rx.recv().await {
+                    Ok(msg)
+                        if msg.id > live
+                            && wanted(
+                                &msg,
+                                room.as_deref(),
+                                username.as_deref(),
+                                session.as_deref(),
+                            )
+                            && !muted(mutes, username.as_deref(), &msg) =>
+                    {
+                        return Some(msg)
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        };
+
+        select! {
+            next = time::timeout(Duration::from_secs(wait), next) => {
+                messages.extend(next.ok().flatten());
+            },
+            _ = &mut end => {},
+        }
+
+        // pick up anything else that landed right behind it
+        while let Ok(msg) = rx.try_recv() {
+            if msg.id > live
+                && wanted(
+                    &msg,
+                    room.as_deref(),
+                    username.as_deref(),
+                    session.as_deref(),
+                )
+                && !muted(mutes, username.as_deref(), &msg)
+            {
+                messages.push(msg);
+            }
+        }
+    }
+
+    let cursor = messages.last().map_or(since, |msg| msg.id);
+    Ok(Json(Poll { messages, cursor }))
+}
+
+// the server's clock in milliseconds since the unix epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+// whether `msg` should go to a subscriber with these optional room,
+// username and session filters. messages meant for one user only go to that
+// user, or just one of their sessions, and self-check probes go to nobody
+fn wanted(
+    msg: &Message,
+    room: Option<&str>,
+    username: Option<&str>,
+    session: Option<&str>,
+) -> bool {
+    !msg.probe
+        && room.is_none_or(|room| room == msg.room)
+        && msg.to.as_deref().is_none_or(|to| Some(to) == username)
+        && msg
+            .to_session
+            .as_deref()
+            .is_none_or(|to| Some(to) == session)
+}
+
+// the last event on a signed-in stream that's been open too long; the client
+// should reconnect with a current token
+fn reauth_required(lifetime: Option<Duration>) -> Event {
+    let lifetime = lifetime.map_or(0, |lifetime| lifetime.as_secs());
+    Event::json(&json!({ "lifetime": lifetime })).event("reauth_required")
+}
+
+// the name someone without an account goes by, normalized if the config
+// says so. an error if it could be mistaken for an account holder's, or if
+// normalizing stretched it past what the forms allow
+fn anonymous_name(config: &Config, username: &str) -> Result<String, &'static str> {
+    let username = match config.normalize_usernames {
+        true => names::normalize(username),
+        false => username.to_string(),
+    };
+    if username.len() >= 20 {
+        return Err("username is too long");
+    }
+
+    let accounts = config
+        .accounts
+        .values()
+        .map(|account| account.username.as_str());
+    match config.protect_account_names && names::looks_like(&username, accounts) {
+        true => Err("username belongs to an account"),
+        false => Ok(username),
+    }
+}
+
+// the name /events and /poll pick up messages for: the same one /message
+// would post under, when the request gives one
+fn listening_name(
+    config: &Config,
+    user: Option<User>,
+    username: Option<String>,
+) -> Result<Option<String>, (Status, &'static str)> {
+    match (username, user) {
+        (Some(_), Some(User(account))) => Ok(Some(account.username)),
+        (Some(name), None) => anonymous_name(config, &name)
+            .map(Some)
+            .map_err(|error| (Status::UnprocessableEntity, error)),
+        (None, _) => Ok(None),
+    }
+}
+
+// the name a request acts under: the account's if it's signed in, or else
+// `username` as someone without an account. a 422 if that name won't do
+fn acting_name(
+    config: &Config,
+    user: Option<&User>,
+    username: &str,
+) -> Result<String, (Status, Value)> {
+    match user {
+        Some(User(account)) => Ok(account.username.clone()),
+        None => anonymous_name(config, username).map_err(|error| forms::invalid("username", error)),
+    }
+}
+
+// the name `username` appears under to others: their account's if they're
+// signed in, the pseudonym for it when anonymizing. `None` if it could pass
+// for someone else's account
+fn shown_name(config: &Config, user: Option<User>, username: &str) -> Option<String> {
+    let name = match user {
+        Some(User(account)) => account.username,
+        None => anonymous_name(config, username).ok()?,
+    };
+
+    match config.anonymize {
+        true => Some(anonymize::pseudonym(&name)),
+        false => Some(name),
+    }
+}
+
+// whether `username` has muted the sender of `msg`
+fn muted(mutes: &Mutes, username: Option<&str>, msg: &Message) -> bool {
+    username.is_some_and(|username| mutes.is_muted(username, &msg.username))
+}
+
+// Self-Check Endpoint: a deeper readiness check than "the server answers".
+// sends a probe through the broadcast channel and waits to hear it back,
+// reporting 503 if it doesn't arrive in time
+#[get("/selfcheck")]
+async fn selfcheck(
+    queue: &State<Sender<Message>>,
+    config: &State<Config>,
+) -> (Status, Json<Value>) {
+    match round_trip(queue, queue.subscribe(), config.selfcheck_timeout()).await {
+        Ok(took) => (
+            Status::Ok,
+            Json(json!({ "status": "ok", "round_trip_ms": took.as_millis() as u64 })),
+        ),
+        Err(error) => (
+            Status::ServiceUnavailable,
+            Json(json!({ "status": "unhealthy", "error": error })),
+        ),
+    }
+}
+
+// how long a probe sent on `queue` takes to reach `rx`, or why it didn't
+// within `wait`
+async fn round_trip(
+    queue: &Sender<Message>,
+    mut rx: Receiver<Message>,
+    wait: Duration,
+) -> Result<Duration, &'static str> {
+    // tells concurrent checks' probes apart
+    static PROBES: AtomicU64 = AtomicU64::new(0);
+
+    let nonce = PROBES.fetch_add(1, Ordering::Relaxed).to_string();
+    let mut probe = Message::new("selfcheck".into(), "selfcheck".into(), nonce.clone());
+    probe.probe = true;
+
+    let start = Instant::now();
+    queue
+        .send(probe)
+        .map_err(|_| "broadcast channel is closed")?;
+
+    let heard = async {
+        loop {
+            match rx.recv().await {
+                Ok(msg) if msg.probe && msg.message == nonce => return Ok(start.elapsed()),
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return Err("broadcast channel is closed"),
+            }
         }
+    };
+
+    time::timeout(wait, heard)
+        .await
+        .unwrap_or(Err("probe didn't come back in time"))
+}
+
+// Gap Report Endpoint: clients tracking ids can say when some never showed
+// up, so out-of-order or lost deliveries are visible to operators. the gap
+// is logged and counted in /metrics. 422 for a gap past the last id handed
+// out or wider than the config allows. limited per ip, with a 429 past that
+#[post("/gap-report", data = "<form>")]
+fn gap_report(
+    form: ChatForm<Gap>,
+    ip: Option<IpAddr>,
+    reporters: &State<Rates<IpAddr>>,
+    history: &State<History>,
+    metrics: &State<Metrics>,
+    config: &State<Config>,
+) -> Result<(), (Status, Value)> {
+    let gap = form.into_inner();
+    if gap.from > gap.to {
+        return Err(forms::invalid("to", "the gap has to end after it starts"));
     }
+    if gap.to > history.last_id() {
+        return Err(forms::invalid("to", "no message has that id yet"));
+    }
+    let missing = (gap.to - gap.from).saturating_add(1);
+    if config.max_gap_span > 0 && missing > config.max_gap_span {
+        let error = format!("gaps can cover at most {} ids", config.max_gap_span);
+        return Err(forms::invalid("to", error));
+    }
+
+    let minute = Duration::from_secs(60);
+    if let Some(ip) = ip {
+        if !reporters.allow(ip, config.gap_reports_per_minute, minute) {
+            return Err((
+                Status::TooManyRequests,
+                json!({ "error": "too many gap reports" }),
+            ));
+        }
+    }
+
+    warn!(
+        "client {} missed messages {} to {} in {}",
+        ip.map_or("unknown".to_string(), |ip| ip.to_string()),
+        gap.from,
+        gap.to,
+        gap.room
+    );
+    metrics.record_gap(missing);
+    Ok(())
+}
+
+// Metrics Endpoint
+#[get("/metrics")]
+fn get_metrics(metrics: &State<Metrics>) -> String {
+    metrics.render()
+}
+
+// Config Endpoint: the chat settings the server actually resolved from
+// Rocket.toml, the environment and the defaults, with account tokens left
+// out. admins only
+#[get("/config")]
+fn get_config(admin: Admin, config: &State<Config>) -> Json<&Config> {
+    info!("{} read the config", admin.0.username);
+    Json(config.inner())
+}
+
+// whether a subscriber with `behind` messages waiting is close enough to the
+// channel's `capacity` to be warned, `threshold` being the fraction of the
+// capacity that counts as close. a threshold of 0 turns the warning off
+fn is_slow_consumer(behind: usize, capacity: usize, threshold: f64) -> bool {
+    threshold > 0.0 && behind as f64 >= capacity as f64 * threshold
 }
 
 // the rocket fn will create a main fn that will start our rocket web server
 #[launch]
 fn rocket() -> _ {
     // build creates a new rocket server instance
-    rocket::build()
-        .manage(channel::<Message>(1024).0)
+    app(rocket::build())
+}
+
+// the chat server on top of `rocket`, configured from the `chat` table in its
+// figment
+fn app(rocket: Rocket<Build>) -> Rocket<Build> {
+    let config = Config::from_figment(rocket.figment());
+    let routes = config.enabled_routes(routes![
+        post,
+        crosspost,
+        maintenance,
+        set_away,
+        mute,
+        unmute,
+        save_draft,
+        get_draft,
+        read,
+        set_receipts,
+        report,
+        dismiss_reports,
+        previews,
+        history_range,
+        user_stats,
+        mod_history,
+        events,
+        presence_stream,
+        firehose,
+        poll,
+        selfcheck,
+        gap_report,
+        get_metrics,
+        get_config
+    ]);
+
+    let static_dir = config.static_dir().map(Path::to_path_buf);
+    let mut rocket = rocket
+        .manage(channel::<Message>(config.channel_capacity).0)
+        .manage(channel::<Notice>(16).0)
+        .manage(channel::<Report>(config.channel_capacity).0)
+        .manage(Reports::default())
+        .manage(Cooldowns::default())
+        .manage(Away::new(&config))
+        .manage(Mutes::new(&config))
+        .manage(Drafts::default())
+        .manage(Presence::new(config.channel_capacity))
+        .manage(Receipts::new(&config))
+        .manage(History::new(&config))
+        .manage(Metrics::default())
+        // waiting /poll requests per ip
+        .manage(Slots::<IpAddr>::default())
+        // gap reports per ip
+        .manage(Rates::<IpAddr>::default())
+        .manage(config.clone())
         // mount our routes
-        .mount("/", routes![post, events])
-        // mount a handler that will serve static files
-        .mount("/", FileServer::from(relative!("static")))
+        .mount("/", routes);
+
+    // a resolver managed before this one knows more than `NoGeo` does
+    if rocket.state::<Box<dyn GeoResolver>>().is_none() {
+        rocket = rocket.manage(Box::new(NoGeo) as Box<dyn GeoResolver>);
+    }
+
+    if config.form_error_details {
+        rocket = rocket.register("/", catchers![forms::unprocessable, forms::too_large]);
+    }
+
+    // mount a handler that will serve static files, unless this is an
+    // api-only deployment. a missing directory would otherwise just look
+    // like a broken frontend, so either stop here or say so loudly
+    if let Some(dir) = static_dir {
+        if !dir.is_dir() {
+            if config.require_static_dir {
+                panic!(
+                    "aborting: static directory {} does not exist",
+                    dir.display()
+                );
+            }
+
+            let warning = format!("static directory {} does not exist", dir.display());
+            rocket = rocket.attach(AdHoc::on_liftoff("Static Files Check", |_| {
+                Box::pin(async move {
+                    warn!("{}; the frontend will 404 until it's created", warning);
+                })
+            }));
+        }
+
+        rocket = rocket.mount("/", FileServer::new(dir, Options::Index | Options::Missing));
+    }
+
+    if !config.announcements.is_empty() {
+        rocket = rocket.attach(AdHoc::on_liftoff("Announcements", |rocket| {
+            Box::pin(async move {
+                let config = rocket.state::<Config>().expect("config is managed");
+                let history = rocket.state::<History>().expect("history is managed");
+                let queue = rocket.state::<Sender<Message>>().expect("queue is managed");
+                announcements::start(&config.announcements, history, queue, rocket.shutdown());
+            })
+        }));
+    }
+
+    rocket
 }
+
+#[cfg(test)]
+mod tests;
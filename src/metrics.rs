@@ -0,0 +1,129 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// counters served at /metrics in prometheus' text format
+#[derive(Default)]
+pub struct Metrics {
+    // live messages timed from the post being accepted to the message being
+    // yielded to a subscriber. each is timed once, by the first stream to get
+    // it, so busy rooms don't count for more than quiet ones
+    latency_samples: AtomicU64,
+    // the newest message id timed so far
+    latency_sampled: AtomicU64,
+    // how many of those made it within the latency slo
+    latency_within_slo: AtomicU64,
+    // gaps in the ids clients saw, as they reported them
+    gap_reports: AtomicU64,
+    // how many ids those gaps covered
+    gap_messages: AtomicU64,
+}
+
+impl Metrics {
+    // time message `id` if no stream has yet: anything at or below the
+    // newest timed id has had its sample taken
+    pub fn record_latency(&self, id: u64, latency: Duration, slo: Duration) {
+        if self.latency_sampled.fetch_max(id, Ordering::Relaxed) >= id {
+            return;
+        }
+        self.latency_samples.fetch_add(1, Ordering::Relaxed);
+        if latency <= slo {
+            self.latency_within_slo.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_gap(&self, missing: u64) {
+        self.gap_reports.fetch_add(1, Ordering::Relaxed);
+        self.gap_messages.fetch_add(missing, Ordering::Relaxed);
+    }
+
+    // share of timed deliveries that met the slo, as a percentage. with
+    // nothing measured yet there's nothing to have missed
+    pub fn slo_compliance(&self) -> f64 {
+        let samples = self.latency_samples.load(Ordering::Relaxed);
+        let within = self.latency_within_slo.load(Ordering::Relaxed);
+        match samples {
+            0 => 100.0,
+            _ => within as f64 * 100.0 / samples as f64,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+
+        metric(
+            "chat_broadcast_latency_samples_total",
+            "counter",
+            "Live messages timed from post to their first subscriber.",
+            self.latency_samples.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "chat_broadcast_latency_within_slo_total",
+            "counter",
+            "Timed messages that met the latency SLO.",
+            self.latency_within_slo.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "chat_broadcast_latency_slo_compliance_percent",
+            "gauge",
+            "Percentage of timed messages that met the latency SLO.",
+            format!("{:.2}", self.slo_compliance()),
+        );
+        metric(
+            "chat_gap_reports_total",
+            "counter",
+            "Gaps in message ids reported by clients.",
+            self.gap_reports.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "chat_gap_messages_total",
+            "counter",
+            "Message ids missing across reported gaps.",
+            self.gap_messages.load(Ordering::Relaxed).to_string(),
+        );
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compliance_is_the_share_within_the_slo() {
+        let metrics = Metrics::default();
+        assert_eq!(metrics.slo_compliance(), 100.0);
+
+        let slo = Duration::from_millis(100);
+        metrics.record_latency(1, Duration::from_millis(20), slo);
+        metrics.record_latency(2, Duration::from_millis(100), slo);
+        metrics.record_latency(3, Duration::from_millis(150), slo);
+        metrics.record_latency(4, Duration::from_secs(2), slo);
+        assert_eq!(metrics.slo_compliance(), 50.0);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("chat_broadcast_latency_samples_total 4\n"));
+        assert!(rendered.contains("chat_broadcast_latency_within_slo_total 2\n"));
+        assert!(rendered.contains("chat_broadcast_latency_slo_compliance_percent 50.00\n"));
+    }
+
+    #[test]
+    fn each_message_is_timed_once() {
+        let metrics = Metrics::default();
+        let slo = Duration::from_millis(100);
+        metrics.record_latency(1, Duration::from_millis(20), slo);
+        metrics.record_latency(1, Duration::from_secs(2), slo);
+        metrics.record_latency(2, Duration::from_secs(2), slo);
+        metrics.record_latency(1, Duration::from_secs(2), slo);
+        assert_eq!(metrics.latency_samples.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.slo_compliance(), 50.0);
+    }
+}
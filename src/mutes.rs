@@ -0,0 +1,101 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::Instant,
+};
+
+use crate::{config::Config, limits::Names};
+
+// who each user has muted. it only changes what the muter is sent; the muted
+// user's messages still go to everyone else, and nothing tells them
+pub struct Mutes {
+    // username -> who they've muted, and when they last muted someone
+    mutes: Mutex<HashMap<String, (HashSet<String>, Instant)>>,
+    names: Names,
+}
+
+impl Mutes {
+    pub fn new(config: &Config) -> Self {
+        Mutes {
+            mutes: Mutex::default(),
+            names: Names::new(config),
+        }
+    }
+
+    // stop sending `target`'s messages to `username`. false if they already
+    // have `limit` others muted (0 for no limit)
+    pub fn mute(&self, username: &str, target: &str, limit: usize) -> bool {
+        let mut mutes = self.mutes.lock().unwrap();
+        self.names.make_room(&mut mutes, username, |(_, at)| *at);
+        let (muted, at) = mutes
+            .entry(username.to_string())
+            .or_insert_with(|| (HashSet::new(), Instant::now()));
+        if limit > 0 && muted.len() >= limit && !muted.contains(target) {
+            return false;
+        }
+
+        muted.insert(target.to_string());
+        *at = Instant::now();
+        true
+    }
+
+    pub fn unmute(&self, username: &str, target: &str) {
+        let mut mutes = self.mutes.lock().unwrap();
+        if let Some((muted, _)) = mutes.get_mut(username) {
+            muted.remove(target);
+            if muted.is_empty() {
+                mutes.remove(username);
+            }
+        }
+    }
+
+    pub fn is_muted(&self, username: &str, target: &str) -> bool {
+        let mutes = self.mutes.lock().unwrap();
+        mutes
+            .get(username)
+            .is_some_and(|(muted, _)| muted.contains(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Account;
+
+    #[test]
+    fn mutes_are_one_way_and_capped() {
+        let mutes = Mutes::new(&Config::default());
+        assert!(mutes.mute("ann", "ben", 1));
+        assert!(mutes.is_muted("ann", "ben"));
+        assert!(!mutes.is_muted("ben", "ann"));
+        assert!(!mutes.mute("ann", "cal", 1));
+        assert!(mutes.mute("ann", "ben", 1));
+
+        mutes.unmute("ann", "ben");
+        assert!(!mutes.is_muted("ann", "ben"));
+        assert!(mutes.mute("ann", "cal", 1));
+    }
+
+    #[test]
+    fn muters_without_an_account_are_capped() {
+        let mut config = Config {
+            max_anonymous_users: 2,
+            ..Config::default()
+        };
+        let dee = Account {
+            username: "dee".to_string(),
+            role: Default::default(),
+        };
+        config.accounts.insert("dee-token".to_string(), dee);
+        let mutes = Mutes::new(&config);
+        for name in ["dee", "ann", "ben", "cal"] {
+            assert!(mutes.mute(name, "eve", 0));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        assert!(mutes.is_muted("dee", "eve"));
+        assert!(!mutes.is_muted("ann", "eve"));
+        assert!(mutes.is_muted("ben", "eve"));
+        assert!(mutes.is_muted("cal", "eve"));
+    }
+}
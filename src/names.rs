@@ -0,0 +1,47 @@
+// usernames aren't reserved, but account holders' names are protected from
+// look-alikes: "аlice" with a cyrillic "а" shouldn't pass for "alice"
+
+use unicode_normalization::UnicodeNormalization;
+
+// `name` in NFKC, so compatibility variants like fullwidth letters or
+// ligatures become the plain characters they stand for
+pub fn normalize(name: &str) -> String {
+    name.nfkc().collect()
+}
+
+// what `name` looks like, ignoring case: two names with the same skeleton
+// could be mistaken for each other (UTS #39)
+pub fn skeleton(name: &str) -> String {
+    unicode_security::skeleton(&name.to_lowercase()).collect()
+}
+
+// whether `username` could be mistaken for any of `names`
+pub fn looks_like<'a>(username: &str, mut names: impl Iterator<Item = &'a str>) -> bool {
+    let username = skeleton(username);
+    names.any(|name| skeleton(name) == username)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn homoglyphs_share_a_skeleton() {
+        assert_eq!(skeleton("\u{430}lice"), skeleton("alice"));
+        assert_eq!(skeleton("ALICE"), skeleton("alice"));
+        assert!(looks_like("\u{430}lice", ["bob", "alice"].into_iter()));
+        assert!(looks_like("a1ice", ["alice"].into_iter()));
+    }
+
+    #[test]
+    fn distinct_names_pass() {
+        assert!(!looks_like("alicia", ["alice", "bob"].into_iter()));
+        assert!(!looks_like("bobby", ["bob"].into_iter()));
+    }
+
+    #[test]
+    fn compatibility_variants_normalize() {
+        assert_eq!(normalize("\u{ff41}lice"), "alice");
+        assert_eq!(normalize("\u{fb01}sh"), "fish");
+    }
+}
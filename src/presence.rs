@@ -0,0 +1,166 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use rocket::{
+    serde::Serialize,
+    tokio::sync::broadcast::{channel, Receiver, Sender},
+};
+
+// who's connected to which room. a user counts once per room however many
+// streams they have open, and joins/leaves go out on their own channel so
+// chat messages and presence churn don't share a queue
+pub struct Presence {
+    // room -> username -> open streams
+    members: Mutex<HashMap<String, HashMap<String, usize>>>,
+    changes: Sender<Membership>,
+}
+
+// a machine-readable join or leave, so clients can keep a member list
+// without parsing system messages. `count` is the room's size after it
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde", tag = "type", rename = "membership")]
+pub struct Membership {
+    pub action: Action,
+    pub username: String,
+    pub room: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Action {
+    Join,
+    Leave,
+}
+
+impl Presence {
+    pub fn new(capacity: usize) -> Self {
+        Presence {
+            members: Mutex::new(HashMap::new()),
+            changes: channel(capacity).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<Membership> {
+        self.changes.subscribe()
+    }
+
+    // note a new stream from `username` in `room`. the user stays in the
+    // room until the returned `Member` (and any others of theirs) is dropped.
+    // with a `limit` (0 for none), a new user is turned away once the room
+    // has that many, though someone already there can always open another
+    pub fn join(&self, room: &str, username: &str, limit: usize) -> Option<Member<'_>> {
+        let mut members = self.members.lock().unwrap();
+        let users = members.entry(room.to_string()).or_default();
+        if limit > 0 && users.len() >= limit && !users.contains_key(username) {
+            return None;
+        }
+
+        let streams = users.entry(username.to_string()).or_default();
+        *streams += 1;
+
+        if *streams == 1 {
+            let count = users.len();
+            self.announce(Action::Join, room, username, count);
+        }
+
+        Some(Member {
+            presence: self,
+            room: room.to_string(),
+            username: username.to_string(),
+        })
+    }
+
+    // who's in `room` right now, in no particular order
+    pub fn members(&self, room: &str) -> Vec<String> {
+        let members = self.members.lock().unwrap();
+        members
+            .get(room)
+            .map_or_else(Vec::new, |users| users.keys().cloned().collect())
+    }
+
+    // how many users are in `room` right now
+    pub fn count(&self, room: &str) -> usize {
+        let members = self.members.lock().unwrap();
+        members.get(room).map_or(0, HashMap::len)
+    }
+
+    fn leave(&self, room: &str, username: &str) {
+        let mut members = self.members.lock().unwrap();
+        let Some(users) = members.get_mut(room) else {
+            return;
+        };
+        let Some(streams) = users.get_mut(username) else {
+            return;
+        };
+
+        *streams -= 1;
+        if *streams == 0 {
+            users.remove(username);
+            let count = users.len();
+            if count == 0 {
+                members.remove(room);
+            }
+            self.announce(Action::Leave, room, username, count);
+        }
+    }
+
+    fn announce(&self, action: Action, room: &str, username: &str, count: usize) {
+        // nobody listening is fine
+        let _res = self.changes.send(Membership {
+            action,
+            username: username.to_string(),
+            room: room.to_string(),
+            count,
+        });
+    }
+}
+
+// one open stream's place in a room
+pub struct Member<'a> {
+    presence: &'a Presence,
+    room: String,
+    username: String,
+}
+
+impl Drop for Member<'_> {
+    fn drop(&mut self) {
+        self.presence.leave(&self.room, &self.username);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn users_count_once_however_many_streams_they_have() {
+        let presence = Presence::new(16);
+        let mut changes = presence.subscribe();
+
+        let first = presence.join("a", "ann", 0).unwrap();
+        let second = presence.join("a", "ann", 0).unwrap();
+        assert_eq!(presence.count("a"), 1);
+        drop(first);
+        assert_eq!(presence.members("a"), ["ann"]);
+        drop(second);
+        assert_eq!(presence.count("a"), 0);
+
+        let join = changes.try_recv().unwrap();
+        assert_eq!((join.action, join.count), (Action::Join, 1));
+        let leave = changes.try_recv().unwrap();
+        assert_eq!((leave.action, leave.count), (Action::Leave, 0));
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn full_rooms_turn_new_users_away() {
+        let presence = Presence::new(16);
+        let ann = presence.join("a", "ann", 1).unwrap();
+        assert!(presence.join("a", "ben", 1).is_none());
+        // someone already in can always open another stream
+        assert!(presence.join("a", "ann", 1).is_some());
+
+        drop(ann);
+        assert!(presence.join("a", "ben", 1).is_some());
+    }
+}
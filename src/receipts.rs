@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rocket::{
+    serde::Serialize,
+    tokio::{
+        self,
+        sync::broadcast::{channel, Receiver, Sender},
+        time,
+    },
+};
+
+use crate::{config::Config, limits::Names};
+
+// read receipts, so senders can see who's read their messages. they're made
+// from read cursor updates, at most one per user per room every so often,
+// and users can turn theirs off. an update that comes too soon after the
+// last receipt isn't lost: the latest one goes out once the interval is up
+//
+// a receipt is only remembered until the interval is up, since all it's
+// needed for by then is not going backwards, and opt-outs by names without
+// an account are capped like everything else keyed by a name
+#[derive(Clone)]
+pub struct Receipts(Arc<Shared>);
+
+struct Shared {
+    inner: Mutex<Inner>,
+    changes: Sender<Receipt>,
+    names: Names,
+}
+
+#[derive(Default)]
+struct Inner {
+    // users who don't send receipts -> when they turned them off
+    opted_out: HashMap<String, Instant>,
+    // (username, room) -> their last receipt, and any waiting to follow it
+    sent: HashMap<(String, String), Sent>,
+}
+
+struct Sent {
+    at: Instant,
+    id: u64,
+    // the furthest they've read since, to go out when the interval is up
+    pending: Option<u64>,
+}
+
+// `username` has read everything in `room` up to `id`
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Receipt {
+    pub room: String,
+    pub username: String,
+    pub id: u64,
+}
+
+impl Receipts {
+    pub fn new(config: &Config) -> Self {
+        Receipts(Arc::new(Shared {
+            inner: Mutex::new(Inner::default()),
+            changes: channel(config.channel_capacity).0,
+            names: Names::new(config),
+        }))
+    }
+
+    pub fn subscribe(&self) -> Receiver<Receipt> {
+        self.0.changes.subscribe()
+    }
+
+    pub fn set_enabled(&self, username: &str, enabled: bool) {
+        let mut inner = self.0.inner.lock().unwrap();
+        if enabled {
+            inner.opted_out.remove(username);
+        } else {
+            let opted_out = &mut inner.opted_out;
+            self.0.names.make_room(opted_out, username, |at| *at);
+            opted_out.insert(username.to_string(), Instant::now());
+            inner.sent.retain(|(user, _), _| user != username);
+        }
+    }
+
+    // send a receipt for `username` reading `room` up to `id`, unless they've
+    // turned receipts off or it isn't past their last one. if their last one
+    // went out less than `interval` ago, this one waits until it's been that
+    // long, and is replaced by any that come after it in the meantime
+    pub fn read(&self, room: &str, username: &str, id: u64, interval: Duration) {
+        let mut inner = self.0.inner.lock().unwrap();
+        if inner.opted_out.contains_key(username) {
+            return;
+        }
+
+        let now = Instant::now();
+        inner
+            .sent
+            .retain(|_, sent| sent.pending.is_some() || now.duration_since(sent.at) < interval);
+        let key = (username.to_string(), room.to_string());
+        let Some(sent) = inner.sent.get_mut(&key) else {
+            inner.sent.insert(
+                key,
+                Sent {
+                    at: now,
+                    id,
+                    pending: None,
+                },
+            );
+            self.send(room, username, id);
+            return;
+        };
+        if id <= sent.pending.unwrap_or(sent.id) {
+            return;
+        }
+
+        let due = sent.at + interval;
+        if now >= due {
+            *sent = Sent {
+                at: now,
+                id,
+                pending: None,
+            };
+            self.send(room, username, id);
+            return;
+        }
+
+        // one flush per wait; later updates just move what it sends along
+        if sent.pending.replace(id).is_none() {
+            let receipts = self.clone();
+            tokio::spawn(async move {
+                time::sleep_until(due.into()).await;
+                receipts.flush(key);
+            });
+        }
+    }
+
+    // send the receipt that was waiting for `key`'s interval to be up, if
+    // it's still wanted
+    fn flush(&self, key: (String, String)) {
+        let mut inner = self.0.inner.lock().unwrap();
+        let Some(sent) = inner.sent.get_mut(&key) else {
+            return;
+        };
+        if let Some(id) = sent.pending.take() {
+            sent.at = Instant::now();
+            sent.id = id;
+            let (username, room) = &key;
+            self.send(room, username, id);
+        }
+    }
+
+    fn send(&self, room: &str, username: &str, id: u64) {
+        // nobody listening is fine
+        let _res = self.0.changes.send(Receipt {
+            room: room.to_string(),
+            username: username.to_string(),
+            id,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOON: Duration = Duration::from_millis(100);
+
+    #[rocket::async_test]
+    async fn the_latest_update_follows_at_the_trailing_edge() {
+        let receipts = Receipts::new(&Config::default());
+        let mut rx = receipts.subscribe();
+        receipts.read("lobby", "ann", 1, SOON);
+        receipts.read("lobby", "ann", 2, SOON);
+        receipts.read("lobby", "ann", 3, SOON);
+        receipts.read("lobby", "ann", 2, SOON);
+
+        assert_eq!(rx.recv().await.unwrap().id, 1);
+        assert!(rx.try_recv().is_err());
+        let trailing = time::timeout(SOON * 3, rx.recv()).await.unwrap().unwrap();
+        assert_eq!(trailing.id, 3);
+
+        time::sleep(SOON * 2).await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[rocket::async_test]
+    async fn opting_out_drops_what_was_waiting() {
+        let receipts = Receipts::new(&Config::default());
+        let mut rx = receipts.subscribe();
+        receipts.read("lobby", "ann", 1, SOON);
+        receipts.read("lobby", "ann", 2, SOON);
+        receipts.set_enabled("ann", false);
+        receipts.read("lobby", "ann", 3, SOON);
+
+        assert_eq!(rx.recv().await.unwrap().id, 1);
+        time::sleep(SOON * 2).await;
+        assert!(rx.try_recv().is_err());
+
+        receipts.set_enabled("ann", true);
+        receipts.read("lobby", "ann", 4, SOON);
+        assert_eq!(rx.try_recv().unwrap().id, 4);
+    }
+
+    #[rocket::async_test]
+    async fn receipts_are_forgotten_once_the_interval_is_up() {
+        let receipts = Receipts::new(&Config::default());
+        receipts.read("lobby", "ann", 1, SOON);
+        receipts.read("hall", "ben", 1, Duration::ZERO);
+        assert_eq!(receipts.0.inner.lock().unwrap().sent.len(), 1);
+    }
+
+    #[test]
+    fn opt_outs_without_an_account_are_capped() {
+        let config = Config {
+            max_anonymous_users: 2,
+            ..Config::default()
+        };
+        let receipts = Receipts::new(&config);
+        for name in ["ann", "ben", "cat"] {
+            receipts.set_enabled(name, false);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let inner = receipts.0.inner.lock().unwrap();
+        assert!(!inner.opted_out.contains_key("ann"));
+        assert!(inner.opted_out.contains_key("ben"));
+        assert!(inner.opted_out.contains_key("cat"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use rocket::serde::Serialize;
+
+// users' reports against messages. each user counts once per message, and a
+// message with enough reports is hidden from replays until a moderator has
+// looked at it
+#[derive(Default)]
+pub struct Reports(Mutex<HashMap<u64, Reported>>);
+
+#[derive(Default)]
+struct Reported {
+    reporters: HashSet<String>,
+    hidden: bool,
+}
+
+// what moderators are told when a message is reported
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Report {
+    pub id: u64,
+    pub room: String,
+    pub reporter: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    // how many users have reported the message so far
+    pub count: usize,
+    pub hidden: bool,
+}
+
+impl Reports {
+    // count `reporter`'s report against message `id`, hiding it once
+    // `threshold` users have (0 never hides). gives the new count and whether
+    // it's hidden, or `None` if they'd already reported it
+    pub fn file(&self, id: u64, reporter: &str, threshold: usize) -> Option<(usize, bool)> {
+        let mut reports = self.0.lock().unwrap();
+        let reported = reports.entry(id).or_default();
+        if !reported.reporters.insert(reporter.to_string()) {
+            return None;
+        }
+
+        let count = reported.reporters.len();
+        if threshold > 0 && count >= threshold {
+            reported.hidden = true;
+        }
+        Some((count, reported.hidden))
+    }
+
+    pub fn is_hidden(&self, id: u64) -> bool {
+        let reports = self.0.lock().unwrap();
+        reports.get(&id).is_some_and(|reported| reported.hidden)
+    }
+
+    // a moderator found nothing wrong, so forget the reports and show the
+    // message again. false if it had none
+    pub fn dismiss(&self, id: u64) -> bool {
+        let mut reports = self.0.lock().unwrap();
+        reports.remove(&id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enough_reporters_hide_a_message() {
+        let reports = Reports::default();
+        assert_eq!(reports.file(1, "ann", 2), Some((1, false)));
+        assert_eq!(reports.file(1, "ann", 2), None);
+        assert!(!reports.is_hidden(1));
+        assert_eq!(reports.file(1, "ben", 2), Some((2, true)));
+        assert!(reports.is_hidden(1));
+        assert!(!reports.is_hidden(2));
+
+        assert!(reports.dismiss(1));
+        assert!(!reports.is_hidden(1));
+        assert!(!reports.dismiss(1));
+    }
+
+    #[test]
+    fn a_zero_threshold_never_hides() {
+        let reports = Reports::default();
+        for reporter in ["ann", "ben", "cat"] {
+            reports.file(1, reporter, 0);
+        }
+        assert!(!reports.is_hidden(1));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Local, TimeZone, Timelike};
+use rocket::serde::Serialize;
+
+use crate::{config::Config, Message};
+
+// running totals per user, kept up as messages are published rather than
+// worked out from the history, so they cover messages that have since been
+// evicted and cost the same however much someone has posted. account
+// holders' are kept for good; anyone can pick any other name, so only so
+// many of those are kept, and the one quiet longest goes first
+pub struct Stats {
+    totals: HashMap<String, Totals>,
+    accounts: HashSet<String>,
+    // totals kept for names without an account; 0 for no limit
+    anonymous_limit: usize,
+    anonymous: usize,
+}
+
+#[derive(Clone, Default)]
+struct Totals {
+    messages: u64,
+    rooms: HashMap<String, u64>,
+    // messages per hour of the day, in the server's local time
+    hours: [u64; 24],
+    first: u64,
+    last: u64,
+}
+
+// what /user-stats reports
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserStats {
+    pub username: String,
+    pub messages: u64,
+    pub most_active_room: String,
+    // 0-23, in the server's local time
+    pub busiest_hour: u32,
+    // milliseconds since the unix epoch
+    pub first_message: u64,
+    pub last_message: u64,
+}
+
+impl Stats {
+    pub fn new(config: &Config) -> Self {
+        Stats {
+            totals: HashMap::new(),
+            accounts: config
+                .accounts
+                .values()
+                .map(|account| account.username.clone())
+                .collect(),
+            anonymous_limit: config.max_anonymous_stats,
+            anonymous: 0,
+        }
+    }
+
+    // count `message` towards its sender, by their real name if it's been
+    // anonymized. crossposts were already counted where they started
+    pub fn record(&mut self, message: &Message) {
+        if message.crossposted_from.is_some() {
+            return;
+        }
+
+        let username = message.real_username.as_ref().unwrap_or(&message.username);
+        if !self.totals.contains_key(username) && !self.accounts.contains(username) {
+            if self.anonymous_limit > 0 && self.anonymous >= self.anonymous_limit {
+                self.evict_quietest();
+            }
+            self.anonymous += 1;
+        }
+        let totals = self.totals.entry(username.clone()).or_default();
+
+        if totals.messages == 0 {
+            totals.first = message.timestamp;
+        }
+        totals.messages += 1;
+        totals.last = message.timestamp;
+        *totals.rooms.entry(message.room.clone()).or_default() += 1;
+        if let Some(at) = Local
+            .timestamp_millis_opt(message.timestamp as i64)
+            .single()
+        {
+            totals.hours[at.hour() as usize] += 1;
+        }
+    }
+
+    // drop the totals of whoever without an account posted least recently
+    fn evict_quietest(&mut self) {
+        let quietest = self
+            .totals
+            .iter()
+            .filter(|(username, _)| !self.accounts.contains(*username))
+            .min_by_key(|(_, totals)| totals.last)
+            .map(|(username, _)| username.clone());
+        if let Some(username) = quietest {
+            self.totals.remove(&username);
+            self.anonymous -= 1;
+        }
+    }
+
+    pub fn get(&self, username: &str) -> Option<UserStats> {
+        let totals = self.totals.get(username)?;
+        // ties go to the earlier hour and the alphabetically first room
+        let (busiest_hour, _) = totals
+            .hours
+            .iter()
+            .enumerate()
+            .max_by_key(|(hour, count)| (**count, std::cmp::Reverse(*hour)))?;
+        let (most_active_room, _) = totals
+            .rooms
+            .iter()
+            .max_by_key(|(room, count)| (**count, std::cmp::Reverse(*room)))?;
+
+        Some(UserStats {
+            username: username.to_string(),
+            messages: totals.messages,
+            most_active_room: most_active_room.clone(),
+            busiest_hour: busiest_hour as u32,
+            first_message: totals.first,
+            last_message: totals.last,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{auth::Account, Origin};
+
+    use super::*;
+
+    fn message(room: &str, username: &str, hour: u32, minute: u32) -> Message {
+        let at = Local.with_ymd_and_hms(2026, 3, 2, hour, minute, 0).unwrap();
+        let mut message = Message::new(room.to_string(), username.to_string(), String::new());
+        message.timestamp = at.timestamp_millis() as u64;
+        message
+    }
+
+    fn stats(max_anonymous_stats: usize) -> Stats {
+        let mut config = Config {
+            max_anonymous_stats,
+            ..Config::default()
+        };
+        let alice = Account {
+            username: "alice".to_string(),
+            role: Default::default(),
+        };
+        config.accounts.insert("alice-token".to_string(), alice);
+        Stats::new(&config)
+    }
+
+    #[test]
+    fn totals_add_up() {
+        let mut stats = stats(0);
+        for (room, hour, minute) in [
+            ("lobby", 9, 0),
+            ("dev", 9, 30),
+            ("dev", 14, 0),
+            ("lobby", 9, 45),
+            ("dev", 20, 0),
+        ] {
+            stats.record(&message(room, "alice", hour, minute));
+        }
+        stats.record(&message("lobby", "bob", 23, 0));
+
+        let alice = stats.get("alice").unwrap();
+        assert_eq!(alice.messages, 5);
+        assert_eq!(alice.most_active_room, "dev");
+        assert_eq!(alice.busiest_hour, 9);
+        assert_eq!(alice.first_message, message("", "", 9, 0).timestamp);
+        assert_eq!(alice.last_message, message("", "", 20, 0).timestamp);
+        assert!(stats.get("carol").is_none());
+    }
+
+    #[test]
+    fn ties_go_to_the_earlier_hour_and_first_room() {
+        let mut stats = stats(0);
+        stats.record(&message("lobby", "alice", 15, 0));
+        stats.record(&message("dev", "alice", 8, 0));
+
+        let alice = stats.get("alice").unwrap();
+        assert_eq!(alice.busiest_hour, 8);
+        assert_eq!(alice.most_active_room, "dev");
+    }
+
+    #[test]
+    fn crossposts_are_not_counted_again() {
+        let mut stats = stats(0);
+        stats.record(&message("lobby", "alice", 9, 0));
+        let mut copy = message("dev", "alice", 9, 1);
+        copy.crossposted_from = Some(Origin {
+            room: "lobby".to_string(),
+            id: 1,
+        });
+        stats.record(&copy);
+
+        let alice = stats.get("alice").unwrap();
+        assert_eq!(alice.messages, 1);
+        assert_eq!(alice.most_active_room, "lobby");
+    }
+
+    #[test]
+    fn only_so_many_names_without_accounts_are_kept() {
+        let mut stats = stats(2);
+        stats.record(&message("lobby", "alice", 8, 0));
+        stats.record(&message("lobby", "ann", 9, 0));
+        stats.record(&message("lobby", "ben", 10, 0));
+        stats.record(&message("lobby", "ann", 11, 0));
+        stats.record(&message("lobby", "cal", 12, 0));
+
+        assert!(stats.get("ben").is_none());
+        assert_eq!(stats.get("ann").unwrap().messages, 2);
+        assert!(stats.get("cal").is_some());
+        assert!(stats.get("alice").is_some());
+    }
+}
@@ -0,0 +1,79 @@
+use regex::Regex;
+use rocket::serde::{Deserialize, Serialize};
+
+// a format every message in a room has to follow, e.g. `\[\w+\] .+` for a
+// deploy log of "[service] what happened". the pattern has to match the
+// whole message, not just part of it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", try_from = "String", into = "String")]
+pub struct Template {
+    pattern: String,
+    regex: Regex,
+}
+
+impl TryFrom<String> for Template {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, regex::Error> {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))?;
+        Ok(Template { pattern, regex })
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> String {
+        template.pattern
+    }
+}
+
+impl Template {
+    // `Err` with what the message should have looked like if it doesn't
+    // fit: the room's `hint` if it has one, or else the pattern itself
+    pub fn check(&self, message: &str, hint: Option<&str>) -> Result<(), String> {
+        if self.regex.is_match(message) {
+            return Ok(());
+        }
+
+        Err(match hint {
+            Some(hint) => format!("messages in this room look like: {}", hint),
+            None => format!("messages in this room must match `{}`", self.pattern),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deploys() -> Template {
+        Template::try_from(r"\[\w+\] .+".to_string()).unwrap()
+    }
+
+    #[test]
+    fn conforming_messages_pass() {
+        assert_eq!(deploys().check("[api] rolled out v2", None), Ok(()));
+    }
+
+    #[test]
+    fn the_whole_message_has_to_match() {
+        assert!(deploys().check("note: [api] rolled out v2", None).is_err());
+        assert!(deploys().check("[api]", None).is_err());
+    }
+
+    #[test]
+    fn rejections_carry_a_hint() {
+        assert_eq!(
+            deploys().check("rolled out", Some("[service] message")),
+            Err("messages in this room look like: [service] message".to_string())
+        );
+        assert_eq!(
+            deploys().check("rolled out", None),
+            Err(r"messages in this room must match `\[\w+\] .+`".to_string())
+        );
+    }
+
+    #[test]
+    fn bad_patterns_are_refused() {
+        assert!(Template::try_from("[".to_string()).is_err());
+    }
+}
@@ -0,0 +1,1796 @@
+// end-to-end tests against a local client. each test starts its own server
+// with whatever `chat` config it needs
+
+use std::{net::IpAddr, time::Duration};
+
+use rocket::{
+    figment::Figment,
+    http::{ContentType, Header, Status},
+    local::asynchronous::{Client, LocalRequest, LocalResponse},
+    serde::json::{self, json, Value},
+    tokio::{io::AsyncReadExt, time},
+    Build, Rocket,
+};
+
+use crate::{
+    app,
+    geo::{Geo, GeoResolver},
+};
+
+// rocket with `chat` as its chat config, before the chat server is set up on
+// it
+fn server(chat: Value) -> Rocket<Build> {
+    let figment = Figment::from(rocket::Config::debug_default())
+        .merge(("log_level", "off"))
+        .merge(("chat", chat));
+    rocket::custom(figment)
+}
+
+// a client for `rocket` with the chat server set up on it, keeping cookies
+// between requests like a browser would
+async fn launch(rocket: Rocket<Build>) -> Client {
+    Client::tracked(app(rocket)).await.expect("valid rocket")
+}
+
+async fn client(chat: Value) -> Client {
+    launch(server(chat)).await
+}
+
+// accounts for tests that need people signed in, by token
+fn accounts() -> Value {
+    json!({
+        "alice-token": { "username": "alice" },
+        "bob-token": { "username": "bob" },
+        "mod-token": { "username": "mod", "role": "moderator" },
+        "admin-token": { "username": "admin", "role": "admin" },
+    })
+}
+
+fn bearer(token: &str) -> Header<'static> {
+    Header::new("Authorization", format!("Bearer {}", token))
+}
+
+fn form<'c>(client: &'c Client, uri: &'static str, body: &str) -> LocalRequest<'c> {
+    client.post(uri).header(ContentType::Form).body(body)
+}
+
+async fn post_json(request: LocalRequest<'_>) -> (Status, Value) {
+    let response = request.dispatch().await;
+    let status = response.status();
+    (status, response.into_json().await.unwrap_or(Value::Null))
+}
+
+async fn get_json(client: &Client, uri: &str) -> Value {
+    let response = client.get(uri.to_string()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    response.into_json().await.expect("json body")
+}
+
+async fn post(client: &Client, body: &str) -> Status {
+    form(client, "/message", body).dispatch().await.status()
+}
+
+// an open server-sent event stream, read an event at a time
+struct Events<'c> {
+    response: LocalResponse<'c>,
+    buffer: Vec<u8>,
+    patience: Duration,
+}
+
+impl<'c> Events<'c> {
+    async fn open(request: LocalRequest<'c>) -> Events<'c> {
+        let response = request.dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        Events {
+            response,
+            buffer: Vec::new(),
+            patience: Duration::from_secs(1),
+        }
+    }
+
+    // wait this long for each event instead
+    fn patience(self, patience: Duration) -> Events<'c> {
+        Events { patience, ..self }
+    }
+
+    async fn get(client: &'c Client, uri: &str) -> Events<'c> {
+        Events::open(client.get(uri.to_string())).await
+    }
+
+    // the next event's name and data, or `None` if nothing comes within the
+    // patience (a second unless set) or the stream ends
+    async fn next(&mut self) -> Option<(String, Value)> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+                let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                let block = String::from_utf8(block).expect("utf-8 events");
+                let mut name = "message".to_string();
+                let mut data = None;
+                for line in block.lines() {
+                    if let Some(value) = line.strip_prefix("event:") {
+                        name = value.trim().to_string();
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        data = Some(json::from_str(value).expect("json data"));
+                    }
+                }
+                // comments and heartbeats carry no data
+                match data {
+                    Some(data) => return Some((name, data)),
+                    None => continue,
+                }
+            }
+
+            let mut chunk = [0; 4096];
+            let read = time::timeout(self.patience, self.response.read(&mut chunk));
+            match read.await {
+                Ok(Ok(read)) if read > 0 => self.buffer.extend_from_slice(&chunk[..read]),
+                _ => return None,
+            }
+        }
+    }
+
+    // the data of the next event called `name`, skipping any others
+    async fn next_named(&mut self, name: &str) -> Option<Value> {
+        loop {
+            let (event, data) = self.next().await?;
+            if event == name {
+                return Some(data);
+            }
+        }
+    }
+
+    // the next `n` chat messages, skipping other events
+    async fn messages(&mut self, n: usize) -> Vec<Value> {
+        let mut messages = Vec::new();
+        while messages.len() < n {
+            match self.next_named("message").await {
+                Some(message) => messages.push(message),
+                None => break,
+            }
+        }
+        messages
+    }
+}
+
+#[rocket::async_test]
+async fn runs_from_one_user_are_continuations() {
+    let client = client(json!({})).await;
+    for body in [
+        "room=lobby&username=ann&message=one",
+        "room=lobby&username=ann&message=two",
+        "room=lobby&username=ben&message=three",
+        "room=other&username=ben&message=four",
+        "room=lobby&username=ben&message=five",
+    ] {
+        assert_eq!(post(&client, body).await, Status::Ok);
+    }
+
+    let mut events = Events::get(&client, "/events?backfill=10").await;
+    let flags: Vec<_> = events
+        .messages(5)
+        .await
+        .iter()
+        .map(|msg| msg["continuation"].as_bool().unwrap())
+        .collect();
+    assert_eq!(flags, [false, true, false, false, true]);
+}
+
+#[rocket::async_test]
+async fn continuations_can_be_turned_off() {
+    let client = client(json!({ "continuation_window": 0 })).await;
+    post(&client, "room=lobby&username=ann&message=one").await;
+    post(&client, "room=lobby&username=ann&message=two").await;
+
+    let mut events = Events::get(&client, "/events?backfill=10").await;
+    for msg in events.messages(2).await {
+        assert_eq!(msg["continuation"], false);
+    }
+}
+
+#[rocket::async_test]
+async fn read_cursors_replay_what_was_missed() {
+    let client = client(json!({})).await;
+    for n in 1..=3 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+    post(&client, "room=other&username=ann&message=elsewhere").await;
+
+    // the cookie from /read is what /events resumes from
+    let read = form(&client, "/read", "room=lobby&id=1").dispatch().await;
+    assert_eq!(read.status(), Status::Ok);
+
+    let mut events = Events::get(&client, "/events").await;
+    let replayed: Vec<_> = events
+        .messages(3)
+        .await
+        .iter()
+        .map(|msg| msg["id"].clone())
+        .collect();
+    assert_eq!(replayed, [json!(2), json!(3)]);
+}
+
+#[rocket::async_test]
+async fn read_cursors_can_be_ignored() {
+    let client = client(json!({ "resume": false })).await;
+    post(&client, "room=lobby&username=ann&message=one").await;
+    form(&client, "/read", "room=lobby&id=0").dispatch().await;
+
+    let mut events = Events::get(&client, "/events").await;
+    assert!(events.messages(1).await.is_empty());
+}
+
+// places every poster in the same made-up country
+struct Everywhere;
+
+impl GeoResolver for Everywhere {
+    fn resolve(&self, _ip: IpAddr) -> Option<Geo> {
+        Some(Geo {
+            country: "NZ".into(),
+            region: None,
+        })
+    }
+}
+
+async fn geo_client(geo_hints: bool) -> Client {
+    let chat = json!({ "geo_hints": geo_hints, "accounts": accounts() });
+    launch(server(chat).manage(Box::new(Everywhere) as Box<dyn GeoResolver>)).await
+}
+
+#[rocket::async_test]
+async fn origin_hints_are_for_moderators_only() {
+    let client = geo_client(true).await;
+    let posted = form(&client, "/message", "room=lobby&username=ann&message=hi")
+        .remote("203.0.113.7:4000".parse().unwrap())
+        .dispatch()
+        .await;
+    assert_eq!(posted.status(), Status::Ok);
+
+    let mut events = Events::get(&client, "/events?backfill=1").await;
+    let public = events.messages(1).await.remove(0);
+    assert!(public.get("geo").is_none());
+
+    let history = client
+        .get("/mod/history?room=lobby")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    let history: Value = history.into_json().await.unwrap();
+    assert_eq!(history[0]["geo"]["country"], "NZ");
+
+    let denied = client
+        .get("/mod/history?room=lobby")
+        .header(bearer("alice-token"))
+        .dispatch()
+        .await;
+    assert_eq!(denied.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn origin_hints_are_off_by_default() {
+    let client = geo_client(false).await;
+    form(&client, "/message", "room=lobby&username=ann&message=hi")
+        .remote("203.0.113.7:4000".parse().unwrap())
+        .dispatch()
+        .await;
+
+    let history = client
+        .get("/mod/history?room=lobby")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    let history: Value = history.into_json().await.unwrap();
+    assert_eq!(history[0]["geo"], Value::Null);
+}
+
+#[rocket::async_test]
+async fn demo_mode_shows_pseudonyms() {
+    let client = client(json!({ "anonymize": true, "accounts": accounts() })).await;
+    post(&client, "room=lobby&username=ann&message=hi").await;
+    post(&client, "room=lobby&username=ann&message=again").await;
+
+    let mut events = Events::get(&client, "/events?backfill=2").await;
+    let messages = events.messages(2).await;
+    let name = crate::anonymize::pseudonym("ann");
+    assert_eq!(messages[0]["username"], name.as_str());
+    assert_eq!(messages[1]["username"], name.as_str());
+    assert!(messages
+        .iter()
+        .all(|msg| msg.get("real_username").is_none()));
+
+    // moderators can still tell who it was
+    let history = client
+        .get("/mod/history?room=lobby")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    let history: Value = history.into_json().await.unwrap();
+    assert_eq!(history[0]["real_username"], "ann");
+}
+
+fn ids(messages: &[Value]) -> Vec<u64> {
+    messages
+        .iter()
+        .map(|msg| msg["id"].as_u64().unwrap())
+        .collect()
+}
+
+#[rocket::async_test]
+async fn backfill_runs_straight_into_live_messages() {
+    let client = client(json!({})).await;
+    for n in 1..=3 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+
+    let mut events = Events::get(&client, "/events?room=lobby&backfill=2").await;
+    assert_eq!(ids(&events.messages(2).await), [2, 3]);
+
+    post(&client, "room=lobby&username=ann&message=4").await;
+    assert_eq!(ids(&events.messages(2).await), [4]);
+}
+
+#[rocket::async_test]
+async fn backfill_is_capped_and_shares_the_replay_with_cursors() {
+    let client = client(json!({ "max_backfill": 2 })).await;
+    for n in 1..=4 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+    form(&client, "/read", "room=lobby&id=2").dispatch().await;
+
+    // the cursor turns up 3 and 4, and so does the backfill
+    let mut events = Events::get(&client, "/events?backfill=50").await;
+    assert_eq!(ids(&events.messages(4).await), [3, 4]);
+}
+
+#[test]
+fn slow_consumers_are_those_near_capacity() {
+    use crate::is_slow_consumer;
+
+    assert!(!is_slow_consumer(7, 10, 0.8));
+    assert!(is_slow_consumer(8, 10, 0.8));
+    // 0 turns the warning off
+    assert!(!is_slow_consumer(10, 10, 0.0));
+}
+
+#[rocket::async_test]
+async fn streams_falling_behind_are_warned() {
+    let client = client(json!({ "channel_capacity": 8, "slow_consumer_threshold": 0.5 })).await;
+    let mut events = Events::get(&client, "/events").await;
+    for n in 1..=6 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+
+    let warning = events.next_named("slow_consumer").await.unwrap();
+    assert_eq!(warning, json!({ "behind": 5, "capacity": 8 }));
+    // nothing was dropped on the way
+    assert_eq!(events.messages(6).await.len(), 6);
+}
+
+#[rocket::async_test]
+async fn disabled_endpoints_are_not_mounted() {
+    let client = client(json!({ "disabled_endpoints": ["post", "get_metrics"] })).await;
+    assert_eq!(
+        post(&client, "room=lobby&username=ann&message=hi").await,
+        Status::NotFound
+    );
+    let metrics = client.get("/metrics").dispatch().await;
+    assert_eq!(metrics.status(), Status::NotFound);
+    // everything else still is
+    let poll = client.get("/poll?timeout=0").dispatch().await;
+    assert_eq!(poll.status(), Status::Ok);
+}
+
+#[test]
+#[should_panic(expected = "unknown endpoint `psot`")]
+fn misspelled_endpoints_stop_the_launch() {
+    app(server(json!({ "disabled_endpoints": ["psot"] })));
+}
+
+async fn metrics(client: &Client) -> String {
+    let response = client.get("/metrics").dispatch().await;
+    response.into_string().await.unwrap()
+}
+
+#[rocket::async_test]
+async fn live_deliveries_are_timed() {
+    let client = client(json!({ "latency_slo_ms": 60000 })).await;
+    let mut events = Events::get(&client, "/events").await;
+    post(&client, "room=lobby&username=ann&message=hi").await;
+    events.messages(1).await;
+
+    // the sample is taken once the stream is polled past the message
+    events.next().await;
+    assert!(metrics(&client)
+        .await
+        .contains("chat_broadcast_latency_within_slo_total 1\n"));
+}
+
+#[rocket::async_test]
+async fn each_message_is_timed_once_however_many_streams_get_it() {
+    let client = client(json!({ "latency_slo_ms": 60000 })).await;
+    let mut streams = Vec::new();
+    for _ in 0..5 {
+        streams.push(Events::get(&client, "/events").await);
+    }
+    post(&client, "room=lobby&username=ann&message=hi").await;
+    for stream in &mut streams {
+        stream.messages(1).await;
+        stream.next().await;
+    }
+
+    let metrics = metrics(&client).await;
+    assert!(
+        metrics.contains("chat_broadcast_latency_samples_total 1\n"),
+        "{metrics}"
+    );
+}
+
+#[rocket::async_test]
+async fn long_polls_return_what_is_buffered_or_wait_for_more() {
+    let client = client(json!({})).await;
+    post(&client, "room=lobby&username=ann&message=one").await;
+    post(&client, "room=lobby&username=ann&message=two").await;
+
+    let buffered = get_json(&client, "/poll?since=0").await;
+    assert_eq!(ids(buffered["messages"].as_array().unwrap()), [1, 2]);
+    assert_eq!(buffered["cursor"], 2);
+
+    let nothing = get_json(&client, "/poll?since=2&timeout=0").await;
+    assert_eq!(nothing, json!({ "messages": [], "cursor": 2 }));
+
+    let (waited, _) = rocket::tokio::join!(get_json(&client, "/poll?since=2&timeout=5"), async {
+        time::sleep(Duration::from_millis(100)).await;
+        post(&client, "room=lobby&username=ann&message=three").await;
+    });
+    assert_eq!(ids(waited["messages"].as_array().unwrap()), [3]);
+    assert_eq!(waited["cursor"], 3);
+}
+
+#[rocket::async_test]
+async fn each_ip_gets_so_many_waiting_polls() {
+    let client = client(json!({ "max_polls_per_ip": 1 })).await;
+    let from = |uri: &'static str| {
+        client
+            .get(uri)
+            .remote("198.51.100.1:5000".parse().unwrap())
+            .dispatch()
+    };
+    let elsewhere = client
+        .get("/poll?timeout=0")
+        .remote("198.51.100.2:5000".parse().unwrap());
+
+    let (waiting, turned_away) = rocket::tokio::join!(from("/poll?timeout=1"), async {
+        time::sleep(Duration::from_millis(100)).await;
+        let other = elsewhere.dispatch().await.status();
+        (from("/poll?timeout=0").await.status(), other)
+    });
+    assert_eq!(waiting.status(), Status::Ok);
+    assert_eq!(turned_away, (Status::TooManyRequests, Status::Ok));
+
+    // the slot is free again once the first poll is done
+    assert_eq!(from("/poll?timeout=0").await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn away_users_auto_reply_to_mentions() {
+    let client = client(json!({})).await;
+    let set = form(&client, "/away", "username=bob&message=back monday")
+        .dispatch()
+        .await;
+    assert_eq!(set.status(), Status::Ok);
+
+    let mut ann = Events::get(&client, "/events?username=ann").await;
+    let mut cat = Events::get(&client, "/events?username=cat").await;
+    post(&client, "room=lobby&username=ann&message=hi @bob!").await;
+    post(&client, "room=lobby&username=ann&message=@bob?").await;
+
+    let seen = ann.messages(3).await;
+    assert_eq!(seen.len(), 3);
+    assert_eq!(seen[1]["username"], "bob");
+    assert_eq!(seen[1]["message"], "back monday");
+    assert_eq!(seen[1]["to"], "ann");
+    // once per cooldown, and only for the sender
+    assert_eq!(seen[2]["message"], "@bob?");
+    assert_eq!(cat.messages(3).await.len(), 2);
+
+    // posting is coming back
+    post(&client, "room=lobby&username=bob&message=hello").await;
+    post(&client, "room=lobby&username=cat&message=@bob").await;
+    assert_eq!(cat.messages(3).await.len(), 2);
+}
+
+#[rocket::async_test]
+async fn away_notes_belong_to_their_account() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    let (status, body) = post_json(form(&client, "/away", "username=alice&message=gone")).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["errors"][0]["field"], "username");
+
+    let signed_in = form(&client, "/away", "username=whoever&message=gone")
+        .header(bearer("alice-token"))
+        .dispatch()
+        .await;
+    assert_eq!(signed_in.status(), Status::Ok);
+
+    let mut ann = Events::get(&client, "/events?username=ann").await;
+    post(&client, "room=lobby&username=ann&message=@alice").await;
+    let seen = ann.messages(2).await;
+    assert_eq!(seen[1]["username"], "alice");
+    assert_eq!(seen[1]["message"], "gone");
+}
+
+#[rocket::async_test]
+async fn demo_mode_away_replies_answer_to_pseudonyms() {
+    let client = client(json!({ "anonymize": true })).await;
+    form(&client, "/away", "username=bob&message=later")
+        .dispatch()
+        .await;
+    let mut ann = Events::get(&client, "/events?username=ann").await;
+
+    // the real name gives nothing away
+    post(&client, "room=lobby&username=ann&message=@bob").await;
+    let bob = crate::anonymize::pseudonym("bob");
+    post(
+        &client,
+        &format!("room=lobby&username=ann&message=@{}", bob),
+    )
+    .await;
+
+    let seen = ann.messages(3).await;
+    assert_eq!(seen.len(), 3);
+    assert_eq!(seen[2]["username"], bob.as_str());
+    assert_eq!(seen[2]["message"], "later");
+}
+
+#[rocket::async_test]
+async fn the_firehose_is_for_admins() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    let anonymous = client.get("/firehose").dispatch().await;
+    assert_eq!(anonymous.status(), Status::Unauthorized);
+    let moderator = client
+        .get("/firehose")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    assert_eq!(moderator.status(), Status::Forbidden);
+
+    let mut firehose = Events::open(client.get("/firehose").header(bearer("admin-token"))).await;
+    form(&client, "/away", "username=dan&message=out")
+        .dispatch()
+        .await;
+    post(&client, "room=a&username=ann&message=@dan").await;
+    post(&client, "room=b&username=cat&message=hi").await;
+    client.get("/selfcheck").dispatch().await;
+
+    // replies meant for one user included, probes not
+    let seen = firehose.messages(4).await;
+    assert_eq!(seen.len(), 3);
+    assert_eq!(seen[1]["to"], "ann");
+    assert_eq!(seen[2]["room"], "b");
+    assert!(seen[2].get("real_username").is_some());
+}
+
+#[rocket::async_test]
+async fn the_firehose_skips_past_its_rate() {
+    let client = client(json!({ "accounts": accounts(), "firehose_rate_limit": 1 })).await;
+    let mut firehose = Events::open(client.get("/firehose").header(bearer("admin-token"))).await;
+    for n in 1..=3 {
+        post(&client, &format!("room=a&username=ann&message={}", n)).await;
+    }
+    // the stream waits a second for more before giving up
+    let sent = firehose.messages(3).await;
+    assert_eq!(sent.len(), 1);
+
+    post(&client, "room=a&username=ann&message=4").await;
+    assert_eq!(
+        firehose.next().await.unwrap(),
+        ("skipped".into(), json!({ "skipped": 2 }))
+    );
+    assert_eq!(firehose.messages(1).await[0]["message"], "4");
+}
+
+#[rocket::async_test]
+async fn the_frontend_is_served_from_the_static_dir() {
+    let served = client(json!({})).await;
+    assert_eq!(served.get("/").dispatch().await.status(), Status::Ok);
+
+    let api_only = client(json!({ "serve_static": false })).await;
+    assert_eq!(
+        api_only.get("/").dispatch().await.status(),
+        Status::NotFound
+    );
+}
+
+#[test]
+#[should_panic(expected = "does not exist")]
+fn a_required_static_dir_stops_the_launch_when_missing() {
+    app(server(
+        json!({ "static_dir": "/nonexistent/static", "require_static_dir": true }),
+    ));
+}
+
+#[rocket::async_test]
+async fn a_missing_static_dir_only_warns_by_default() {
+    let client = client(json!({ "static_dir": "/nonexistent/static" })).await;
+    assert_eq!(client.get("/").dispatch().await.status(), Status::NotFound);
+    assert_eq!(
+        post(&client, "room=lobby&username=ann&message=hi").await,
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn badges_come_from_the_account() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    form(&client, "/message", "room=lobby&username=x&message=hi")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    post(&client, "room=lobby&username=ann&message=hi&role=admin").await;
+
+    let mut events = Events::get(&client, "/events?backfill=2").await;
+    let seen = events.messages(2).await;
+    assert_eq!(seen[0]["username"], "mod");
+    assert_eq!(seen[0]["role"], "moderator");
+    assert_eq!(seen[1]["role"], "member");
+}
+
+#[rocket::async_test]
+async fn badges_can_be_turned_off() {
+    let client = client(json!({ "accounts": accounts(), "role_badges": false })).await;
+    form(&client, "/message", "room=lobby&username=x&message=hi")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+
+    let mut events = Events::get(&client, "/events?backfill=1").await;
+    assert!(events.messages(1).await[0].get("role").is_none());
+}
+
+#[rocket::async_test]
+async fn joins_and_leaves_are_announced() {
+    let client = client(json!({})).await;
+    let mut watcher = Events::get(&client, "/events?room=lobby").await;
+
+    let ann = Events::get(&client, "/events?room=lobby&username=ann").await;
+    let join = watcher.next_named("membership").await.unwrap();
+    assert_eq!(
+        join,
+        json!({ "type": "membership", "action": "join", "username": "ann", "room": "lobby", "count": 1 })
+    );
+
+    drop(ann);
+    let leave = watcher.next_named("membership").await.unwrap();
+    assert_eq!(leave["action"], "leave");
+    assert_eq!(leave["count"], 0);
+}
+
+#[rocket::async_test]
+async fn membership_events_can_be_left_off_the_chat_stream() {
+    let client = client(json!({ "membership_events": false })).await;
+    let mut watcher = Events::get(&client, "/events?room=lobby").await;
+    let _ann = Events::get(&client, "/events?room=lobby&username=ann").await;
+    assert!(watcher.next_named("membership").await.is_none());
+}
+
+#[rocket::async_test]
+async fn moderators_can_cross_post() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    post(&client, "room=a&username=ann&message=worth sharing").await;
+
+    let denied = form(&client, "/crosspost", "id=1&room=b")
+        .header(bearer("alice-token"))
+        .dispatch()
+        .await;
+    assert_eq!(denied.status(), Status::Forbidden);
+    let missing = form(&client, "/crosspost", "id=99&room=b")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    assert_eq!(missing.status(), Status::NotFound);
+
+    let mut room_b = Events::get(&client, "/events?room=b").await;
+    let (status, copy) =
+        post_json(form(&client, "/crosspost", "id=1&room=b").header(bearer("mod-token"))).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(copy["crossposted_from"], json!({ "room": "a", "id": 1 }));
+
+    let seen = room_b.messages(1).await.remove(0);
+    assert_eq!(seen["id"], 2);
+    assert_eq!(seen["username"], "ann");
+    assert_eq!(seen["message"], "worth sharing");
+    assert_eq!(seen["crossposted_from"]["id"], 1);
+}
+
+#[rocket::async_test]
+async fn admins_warn_streams_of_maintenance() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    let denied = form(&client, "/maintenance", "message=restart&downtime=60")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    assert_eq!(denied.status(), Status::Forbidden);
+
+    let mut events = Events::get(&client, "/events?room=lobby").await;
+    let before = crate::now_millis();
+    let (status, notice) = post_json(
+        form(&client, "/maintenance", "message=restart&downtime=60").header(bearer("admin-token")),
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+
+    let sent = events.next_named("maintenance_notice").await.unwrap();
+    assert_eq!(sent, notice);
+    assert_eq!(sent["downtime"], 60);
+    assert!(sent["back_at"].as_u64().unwrap() >= before + 60_000);
+}
+
+#[rocket::async_test]
+async fn capped_rooms_turn_users_away_once_full() {
+    let rooms = json!({ "support": { "max_users": 1 } });
+    let client = client(json!({ "accounts": accounts(), "rooms": rooms })).await;
+
+    let ann = Events::get(&client, "/events?room=support&username=ann").await;
+    let ben = client
+        .get("/events?room=support&username=ben")
+        .dispatch()
+        .await;
+    assert_eq!(ben.status(), Status::TooManyRequests);
+    // ann can have it open twice
+    let _again = Events::get(&client, "/events?room=support&username=ann").await;
+    // a stream nobody can be counted for doesn't get in at all
+    let nobody = client.get("/events?room=support").dispatch().await;
+    assert_eq!(nobody.status(), Status::UnprocessableEntity);
+    // other rooms aren't capped
+    let _lobby = Events::get(&client, "/events?room=lobby").await;
+
+    // moderators get in regardless, and take up a place while they're there
+    let moderator = Events::open(
+        client
+            .get("/events?room=support&username=mod")
+            .header(bearer("mod-token")),
+    )
+    .await;
+
+    drop((ann, _again, moderator));
+    let _ben = Events::get(&client, "/events?room=support&username=ben").await;
+}
+
+#[rocket::async_test]
+async fn content_hashes_are_optional() {
+    for enabled in [true, false] {
+        let client = client(json!({ "content_hashes": enabled })).await;
+        post(&client, "room=lobby&username=ann&message=hi").await;
+        let mut events = Events::get(&client, "/events?backfill=1").await;
+        let message = events.messages(1).await.remove(0);
+        assert_eq!(message["content_hash"].is_string(), enabled);
+    }
+}
+
+#[rocket::async_test]
+async fn the_self_check_times_a_round_trip() {
+    let client = client(json!({})).await;
+    let mut stream = Events::get(&client, "/events").await;
+    let (status, body) = post_json(client.get("/selfcheck")).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["status"], "ok");
+    assert!(body["round_trip_ms"].is_u64());
+
+    // nobody else sees the probe
+    assert!(stream.messages(1).await.is_empty());
+}
+
+#[rocket::async_test]
+async fn a_probe_that_never_arrives_fails_the_self_check() {
+    use rocket::tokio::sync::broadcast::channel;
+
+    // listening somewhere the probe isn't sent, like a stalled subscriber
+    let (queue, _rx) = channel(16);
+    let elsewhere = channel::<crate::Message>(16).0;
+    let heard = crate::round_trip(&queue, elsewhere.subscribe(), Duration::from_millis(50));
+    assert_eq!(heard.await, Err("probe didn't come back in time"));
+}
+
+async fn report(client: &Client, token: &str, body: &str) -> (Status, Value) {
+    post_json(form(client, "/report", body).header(bearer(token))).await
+}
+
+#[rocket::async_test]
+async fn reported_messages_are_hidden_until_dismissed() {
+    let client = client(json!({ "accounts": accounts(), "report_hide_threshold": 2 })).await;
+    post(&client, "room=lobby&username=ann&message=spam").await;
+    post(&client, "room=lobby&username=ann&message=fine").await;
+
+    let anonymous = form(&client, "/report", "id=1").dispatch().await;
+    assert_eq!(anonymous.status(), Status::Unauthorized);
+    assert_eq!(
+        report(&client, "alice-token", "id=99").await.0,
+        Status::NotFound
+    );
+
+    let mut moderator =
+        Events::open(client.get("/events?room=lobby").header(bearer("mod-token"))).await;
+    let (status, first) = report(&client, "alice-token", "id=1&reason=ads").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        (first["count"].clone(), first["hidden"].clone()),
+        (json!(1), json!(false))
+    );
+    assert_eq!(
+        report(&client, "alice-token", "id=1").await.0,
+        Status::Conflict
+    );
+    let (_, second) = report(&client, "bob-token", "id=1").await;
+    assert_eq!(second["hidden"], true);
+
+    let told = moderator.next_named("report").await.unwrap();
+    assert_eq!(told["reporter"], "alice");
+    assert_eq!(told["reason"], "ads");
+
+    let mut replay = Events::get(&client, "/events?backfill=5").await;
+    assert_eq!(ids(&replay.messages(2).await), [2]);
+
+    let dismissed = form(&client, "/report/dismiss", "id=1")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    assert_eq!(dismissed.status(), Status::Ok);
+    let mut replay = Events::get(&client, "/events?backfill=5").await;
+    assert_eq!(ids(&replay.messages(2).await), [1, 2]);
+}
+
+#[rocket::async_test]
+async fn streams_opened_mid_burst_see_each_message_once() {
+    let client = client(json!({})).await;
+    let burst = async {
+        for n in 1..=40 {
+            post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+            rocket::tokio::task::yield_now().await;
+        }
+    };
+    let subscribers = async {
+        let mut streams = Vec::new();
+        for _ in 0..8 {
+            streams.push(Events::get(&client, "/events?backfill=100").await);
+            rocket::tokio::task::yield_now().await;
+        }
+        streams
+    };
+    let ((), streams) = rocket::tokio::join!(burst, subscribers);
+
+    for mut stream in streams {
+        assert_eq!(
+            ids(&stream.messages(40).await),
+            (1..=40).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[rocket::async_test]
+async fn muted_users_are_filtered_from_the_muters_streams_only() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    post(&client, "room=lobby&username=ben&message=before").await;
+    let (status, _) = post_json(form(&client, "/mute", "username=ann&target=ben")).await;
+    assert_eq!(status, Status::Ok);
+
+    let mut muter = Events::get(&client, "/events?username=ann&backfill=10").await;
+    let mut other = Events::get(&client, "/events?username=cal&backfill=10").await;
+    post(&client, "room=lobby&username=ben&message=after").await;
+    post(&client, "room=lobby&username=dan&message=hi").await;
+
+    let shown = muter.messages(3).await;
+    assert_eq!(shown.len(), 1);
+    assert_eq!(shown[0]["username"], "dan");
+    assert_eq!(ids(&other.messages(3).await), [1, 2, 3]);
+
+    let (status, _) = post_json(form(&client, "/unmute", "username=ann&target=ben")).await;
+    assert_eq!(status, Status::Ok);
+    post(&client, "room=lobby&username=ben&message=back").await;
+    assert_eq!(muter.messages(1).await[0]["username"], "ben");
+}
+
+#[rocket::async_test]
+async fn mutes_belong_to_the_muters_account() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    let (status, body) = post_json(form(&client, "/mute", "username=alice&target=ben")).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["errors"][0]["field"], "username");
+
+    let signed_in =
+        form(&client, "/mute", "username=whoever&target=ben").header(bearer("alice-token"));
+    assert_eq!(post_json(signed_in).await.0, Status::Ok);
+    let mut stream = Events::open(
+        client
+            .get("/events?username=alice")
+            .header(bearer("alice-token")),
+    )
+    .await;
+    post(&client, "room=lobby&username=ben&message=hi").await;
+    assert!(stream.messages(1).await.is_empty());
+}
+
+#[rocket::async_test]
+async fn lenient_forms_ignore_unknown_fields() {
+    let client = client(json!({})).await;
+    let status = post(&client, "room=lobby&username=ann&message=hi&client=legacy").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        post(&client, "room=lobby&username=ann").await,
+        Status::UnprocessableEntity
+    );
+}
+
+#[rocket::async_test]
+async fn strict_forms_reject_unknown_fields() {
+    let client = client(json!({ "strict_forms": true })).await;
+    let (status, body) = post_json(form(
+        &client,
+        "/message",
+        "room=lobby&username=ann&message=hi&client=legacy",
+    ))
+    .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["errors"][0]["field"], "client");
+    assert_eq!(
+        post(&client, "room=lobby&username=ann&message=hi").await,
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn single_line_rooms_turn_away_line_breaks() {
+    let client = client(json!({ "rooms": { "standup": { "single_line": true } } })).await;
+    let (status, body) = post_json(form(
+        &client,
+        "/message",
+        "room=standup&username=ann&message=done%0Anext",
+    ))
+    .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(
+        body["errors"][0]["message"],
+        "messages in this room must be one line"
+    );
+    assert_eq!(
+        post(&client, "room=standup&username=ann&message=done%0Dnext").await,
+        Status::UnprocessableEntity
+    );
+    assert_eq!(
+        post(&client, "room=standup&username=ann&message=done").await,
+        Status::Ok
+    );
+    assert_eq!(
+        post(&client, "room=lobby&username=ann&message=done%0Anext").await,
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn occupied_rooms_hear_their_user_count() {
+    let client = client(json!({ "room_stats_interval": 1 })).await;
+    let start = time::Instant::now();
+    let mut ann = Events::get(&client, "/events?room=lobby&username=ann")
+        .await
+        .patience(Duration::from_secs(3));
+    assert_eq!(
+        ann.next_named("room_stats").await.unwrap(),
+        json!({ "room": "lobby", "count": 1 })
+    );
+    assert!(start.elapsed() >= Duration::from_millis(900));
+
+    let _ben = Events::get(&client, "/events?room=lobby&username=ben").await;
+    assert_eq!(ann.next_named("room_stats").await.unwrap()["count"], 2);
+}
+
+#[rocket::async_test]
+async fn empty_rooms_hear_nothing() {
+    let client = client(json!({ "room_stats_interval": 1 })).await;
+    let mut watcher = Events::get(&client, "/events?room=lobby")
+        .await
+        .patience(Duration::from_millis(2500));
+    assert_eq!(watcher.next_named("room_stats").await, None);
+}
+
+#[rocket::async_test]
+async fn every_bad_field_is_reported() {
+    let client = client(json!({})).await;
+    let body = format!(
+        "room={}&username={}&message=hi",
+        "r".repeat(40),
+        "u".repeat(30)
+    );
+    let (status, body) = post_json(form(&client, "/message", &body)).await;
+    // a too-long field is a 413, as rocket sees it
+    assert_eq!(status, Status::PayloadTooLarge);
+
+    let fields: Vec<_> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].as_str().unwrap())
+        .collect();
+    assert!(
+        fields.contains(&"room") && fields.contains(&"username"),
+        "{fields:?}"
+    );
+}
+
+#[rocket::async_test]
+async fn missing_fields_are_reported_together() {
+    let client = client(json!({})).await;
+    let (status, body) = post_json(form(&client, "/message", "room=lobby")).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    let errors = body["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert!(errors
+        .iter()
+        .all(|error| !error["message"].as_str().unwrap().is_empty()));
+}
+
+#[rocket::async_test]
+async fn form_error_details_can_be_turned_off() {
+    let client = client(json!({ "form_error_details": false })).await;
+    let (status, body) = post_json(form(&client, "/message", "room=lobby")).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert!(body.get("errors").is_none());
+}
+
+#[rocket::async_test]
+async fn drafts_come_back_to_their_owner_only() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    let save = form(&client, "/draft", "room=lobby&message=half").header(bearer("alice-token"));
+    assert_eq!(save.dispatch().await.status(), Status::Ok);
+
+    let draft = client
+        .get("/draft?room=lobby")
+        .header(bearer("alice-token"))
+        .dispatch()
+        .await;
+    assert_eq!(draft.into_json::<Value>().await.unwrap()["message"], "half");
+    let other = client
+        .get("/draft?room=lobby")
+        .header(bearer("bob-token"))
+        .dispatch()
+        .await;
+    assert_eq!(other.status(), Status::NotFound);
+    let anonymous = client.get("/draft?room=lobby").dispatch().await;
+    assert_eq!(anonymous.status(), Status::Unauthorized);
+
+    let sent = form(
+        &client,
+        "/message",
+        "room=lobby&username=alice&message=whole",
+    )
+    .header(bearer("alice-token"));
+    assert_eq!(sent.dispatch().await.status(), Status::Ok);
+    let draft = client
+        .get("/draft?room=lobby")
+        .header(bearer("alice-token"))
+        .dispatch()
+        .await;
+    assert_eq!(draft.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn drafts_expire_after_their_ttl() {
+    let client = client(json!({ "accounts": accounts(), "draft_ttl": 1 })).await;
+    let save = form(&client, "/draft", "room=lobby&message=half").header(bearer("alice-token"));
+    assert_eq!(save.dispatch().await.status(), Status::Ok);
+
+    time::sleep(Duration::from_millis(1100)).await;
+    let draft = client
+        .get("/draft?room=lobby")
+        .header(bearer("alice-token"))
+        .dispatch()
+        .await;
+    assert_eq!(draft.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn signed_in_streams_run_out_and_ask_for_a_token() {
+    let client = client(json!({ "accounts": accounts(), "max_stream_lifetime": 1 })).await;
+    let request = client.get("/events").header(bearer("alice-token"));
+    let mut stream = Events::open(request).await.patience(Duration::from_secs(3));
+    assert_eq!(
+        stream.next_named("reauth_required").await.unwrap(),
+        json!({ "lifetime": 1 })
+    );
+    assert_eq!(stream.next().await, None);
+
+    let mut anonymous = Events::get(&client, "/events?username=ann")
+        .await
+        .patience(Duration::from_millis(2500));
+    assert_eq!(anonymous.next_named("reauth_required").await, None);
+}
+
+#[test]
+fn truncation_counts_characters_and_marks_the_cut() {
+    assert_eq!(crate::truncate("hello", 5), "hello");
+    assert_eq!(crate::truncate("hello there", 5), "hello…");
+    assert_eq!(crate::truncate("héllo wörld", 7), "héllo w…");
+    assert_eq!(crate::truncate("", 0), "");
+}
+
+#[rocket::async_test]
+async fn previews_show_each_rooms_latest_message() {
+    let client = client(json!({ "preview_length": 4 })).await;
+    post(&client, "room=lobby&username=ann&message=first").await;
+    post(&client, "room=lobby&username=ben&message=second").await;
+    post(&client, "room=support&username=cal&message=ok").await;
+
+    let previews = get_json(&client, "/previews?rooms=lobby&rooms=support&rooms=empty").await;
+    let previews = previews.as_array().unwrap();
+    assert_eq!(previews.len(), 2);
+    assert_eq!(previews[0]["username"], "ben");
+    assert_eq!(previews[0]["message"], "seco…");
+    assert_eq!(previews[1]["message"], "ok");
+}
+
+#[rocket::async_test]
+async fn previews_are_bounded() {
+    let client = client(json!({ "max_preview_rooms": 2 })).await;
+    let too_many = client
+        .get("/previews?rooms=a&rooms=b&rooms=c")
+        .dispatch()
+        .await;
+    assert_eq!(too_many.status(), Status::UnprocessableEntity);
+    let body: Value = too_many.into_json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "rooms");
+
+    let long = format!("/previews?rooms={}", "r".repeat(30));
+    assert_eq!(
+        client.get(long).dispatch().await.status(),
+        Status::UnprocessableEntity
+    );
+    assert_eq!(
+        client
+            .get("/previews?rooms=a&rooms=b")
+            .dispatch()
+            .await
+            .status(),
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn previews_skip_hidden_messages() {
+    let client = client(json!({ "accounts": accounts(), "report_hide_threshold": 1 })).await;
+    post(&client, "room=lobby&username=ann&message=fine").await;
+    post(&client, "room=lobby&username=ann&message=spam").await;
+    report(&client, "alice-token", "id=2").await;
+
+    let previews = get_json(&client, "/previews?rooms=lobby").await;
+    assert_eq!(previews[0]["message"], "fine");
+}
+
+#[rocket::async_test]
+async fn look_alikes_of_account_names_are_turned_away() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    let (status, body) = post_json(form(
+        &client,
+        "/message",
+        "room=lobby&username=%D0%B0lice&message=hi",
+    ))
+    .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(
+        body["errors"][0]["message"],
+        "username belongs to an account"
+    );
+    assert_eq!(
+        post(&client, "room=lobby&username=alicia&message=hi").await,
+        Status::Ok
+    );
+
+    let events = client.get("/events?username=%D0%B0lice").dispatch().await;
+    assert_eq!(events.status(), Status::UnprocessableEntity);
+    let poll = client
+        .get("/poll?username=%D0%B0lice&timeout=0")
+        .dispatch()
+        .await;
+    assert_eq!(poll.status(), Status::UnprocessableEntity);
+    let poll = client
+        .get("/poll?username=alicia&timeout=0")
+        .dispatch()
+        .await;
+    assert_eq!(poll.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn names_are_measured_after_normalizing() {
+    let client = client(json!({})).await;
+    // one character that normalizes to eighteen
+    let (status, body) = post_json(form(
+        &client,
+        "/message",
+        "room=lobby&username=%EF%B7%BA&message=hi",
+    ))
+    .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["errors"][0]["message"], "username is too long");
+}
+
+#[rocket::async_test]
+async fn templated_rooms_explain_their_format() {
+    let rooms = json!({
+        "deploys": { "template": r"\[\w+\] .+", "template_hint": "[service] message" },
+    });
+    let client = client(json!({ "rooms": rooms })).await;
+    let (status, body) = post_json(form(
+        &client,
+        "/message",
+        "room=deploys&username=ann&message=oops",
+    ))
+    .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(
+        body["errors"][0]["message"],
+        "messages in this room look like: [service] message"
+    );
+
+    let fits = "room=deploys&username=ann&message=%5Bapi%5D+rolled+out";
+    assert_eq!(post(&client, fits).await, Status::Ok);
+    assert_eq!(
+        post(&client, "room=lobby&username=ann&message=oops").await,
+        Status::Ok
+    );
+}
+
+#[test]
+#[should_panic(expected = "invalid chat configuration")]
+fn a_bad_template_stops_the_launch() {
+    app(server(
+        json!({ "rooms": { "deploys": { "template": "[" } } }),
+    ));
+}
+
+#[rocket::async_test]
+async fn gap_reports_are_counted_and_rate_limited() {
+    let client = client(json!({ "gap_reports_per_minute": 2 })).await;
+    for n in 0..5 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+
+    let gap = || {
+        form(&client, "/gap-report", "room=lobby&from=2&to=4")
+            .remote("198.51.100.1:5000".parse().unwrap())
+    };
+    assert_eq!(gap().dispatch().await.status(), Status::Ok);
+    assert_eq!(gap().dispatch().await.status(), Status::Ok);
+    assert_eq!(gap().dispatch().await.status(), Status::TooManyRequests);
+
+    let metrics = metrics(&client).await;
+    assert!(metrics.contains("chat_gap_reports_total 2"), "{metrics}");
+    assert!(metrics.contains("chat_gap_messages_total 6"), "{metrics}");
+
+    let elsewhere = gap().remote("198.51.100.2:5000".parse().unwrap());
+    assert_eq!(elsewhere.dispatch().await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn gap_reports_stay_within_the_ids_handed_out() {
+    let client = client(json!({ "max_gap_span": 3 })).await;
+    for n in 0..5 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+
+    for body in [
+        "room=lobby&from=4&to=2",
+        "room=lobby&from=1&to=6",
+        "room=lobby&from=0&to=18446744073709551615",
+        "room=lobby&from=1&to=4",
+    ] {
+        let (status, errors) = post_json(form(&client, "/gap-report", body)).await;
+        assert_eq!(status, Status::UnprocessableEntity, "{body}");
+        assert_eq!(errors["errors"][0]["field"], "to");
+    }
+    let fine = form(&client, "/gap-report", "room=lobby&from=3&to=5");
+    assert_eq!(fine.dispatch().await.status(), Status::Ok);
+}
+
+#[test]
+fn session_targeted_messages_want_that_session() {
+    let mut reply = crate::Message::new("lobby".to_string(), "bob".to_string(), "away".to_string());
+    reply.to = Some("ann".to_string());
+    reply.to_session = Some("phone".to_string());
+
+    assert!(crate::wanted(&reply, None, Some("ann"), Some("phone")));
+    assert!(!crate::wanted(&reply, None, Some("ann"), Some("laptop")));
+    assert!(!crate::wanted(&reply, None, Some("ann"), None));
+    assert!(!crate::wanted(&reply, None, Some("cat"), Some("phone")));
+}
+
+#[rocket::async_test]
+async fn replies_reach_only_the_sending_device() {
+    let client = client(json!({})).await;
+    form(&client, "/away", "username=bob&message=back monday")
+        .dispatch()
+        .await;
+
+    let mut phone = Events::get(&client, "/events?username=ann&session=phone").await;
+    let mut laptop = Events::get(&client, "/events?username=ann&session=laptop").await;
+    let mut unnamed = Events::get(&client, "/events?username=ann").await;
+    post(
+        &client,
+        "room=lobby&username=ann&message=hi @bob&session=phone",
+    )
+    .await;
+
+    let seen = phone.messages(2).await;
+    assert_eq!(seen.len(), 2);
+    assert_eq!(seen[1]["message"], "back monday");
+    assert_eq!(laptop.messages(2).await.len(), 1);
+    assert_eq!(unnamed.messages(2).await.len(), 1);
+}
+
+#[rocket::async_test]
+async fn cursor_moves_become_read_receipts() {
+    let client = client(json!({ "receipt_interval_ms": 200 })).await;
+    let mut stream = Events::get(&client, "/events?room=lobby").await;
+    for id in [1, 2, 3] {
+        let body = format!("room=lobby&id={}&username=ann", id);
+        form(&client, "/read", &body).dispatch().await;
+    }
+
+    let receipt = json!({ "room": "lobby", "username": "ann", "id": 1 });
+    assert_eq!(stream.next_named("read_receipt").await.unwrap(), receipt);
+    assert_eq!(stream.next_named("read_receipt").await.unwrap()["id"], 3);
+}
+
+#[rocket::async_test]
+async fn read_receipts_can_be_turned_off() {
+    let client = client(json!({})).await;
+    let mut stream = Events::get(&client, "/events?room=lobby").await;
+    form(&client, "/receipts", "username=ann&enabled=false")
+        .dispatch()
+        .await;
+    form(&client, "/read", "room=lobby&id=1&username=ann")
+        .dispatch()
+        .await;
+    form(&client, "/read", "room=lobby&id=1&username=ben")
+        .dispatch()
+        .await;
+
+    assert_eq!(
+        stream.next_named("read_receipt").await.unwrap()["username"],
+        "ben"
+    );
+    assert_eq!(stream.next_named("read_receipt").await, None);
+}
+
+#[rocket::async_test]
+async fn due_announcements_go_to_their_room() {
+    let announcements = json!([
+        { "schedule": "* * * * * *", "room": "standup", "message": "standup now" },
+    ]);
+    let client = client(json!({ "announcements": announcements })).await;
+    let mut standup = Events::get(&client, "/events?room=standup")
+        .await
+        .patience(Duration::from_secs(2));
+    let announced = standup.messages(1).await;
+    assert_eq!(announced[0]["message"], "standup now");
+    assert_eq!(announced[0]["username"], "announcements");
+
+    let mut lobby = Events::get(&client, "/events?room=lobby")
+        .await
+        .patience(Duration::from_millis(1500));
+    assert!(lobby.messages(1).await.is_empty());
+}
+
+#[test]
+#[should_panic(expected = "invalid chat configuration")]
+fn a_malformed_schedule_stops_the_launch() {
+    let announcements =
+        json!([{ "schedule": "every morning", "room": "standup", "message": "hi" }]);
+    app(server(json!({ "announcements": announcements })));
+}
+
+#[rocket::async_test]
+async fn hello_advertises_the_rooms_limit() {
+    let rooms =
+        json!({ "tweets": { "max_message_length": 5 }, "essays": { "max_message_length": 0 } });
+    let client = client(json!({ "max_message_length": 10, "rooms": rooms })).await;
+
+    for (room, limit) in [
+        ("tweets", json!(5)),
+        ("lobby", json!(10)),
+        ("essays", Value::Null),
+    ] {
+        let mut stream = Events::get(&client, &format!("/events?room={}", room)).await;
+        let hello = stream.next_named("hello").await.unwrap();
+        assert_eq!(hello["max_message_length"], limit, "{room}");
+    }
+    let mut everywhere = Events::get(&client, "/events").await;
+    assert_eq!(
+        everywhere.next_named("hello").await.unwrap()["room"],
+        Value::Null
+    );
+
+    // the limit advertised is the one enforced
+    assert_eq!(
+        post(&client, "room=tweets&username=ann&message=12345").await,
+        Status::Ok
+    );
+    assert_eq!(
+        post(&client, "room=tweets&username=ann&message=123456").await,
+        Status::UnprocessableEntity
+    );
+}
+
+#[rocket::async_test]
+async fn the_presence_stream_follows_joins_and_leaves_only() {
+    let client = client(json!({ "membership_events": false })).await;
+    let ann = Events::get(&client, "/events?room=lobby&username=ann").await;
+    let mut presence = Events::get(&client, "/presence-stream?room=lobby").await;
+    assert_eq!(
+        presence.next().await.unwrap(),
+        (
+            "members".to_string(),
+            json!({ "room": "lobby", "members": ["ann"] })
+        )
+    );
+
+    let ben = Events::get(&client, "/events?room=lobby&username=ben").await;
+    let _cal = Events::get(&client, "/events?room=support&username=cal").await;
+    post(&client, "room=lobby&username=ann&message=hi").await;
+    drop((ann, ben));
+
+    let mut seen = Vec::new();
+    while let Some((name, data)) = presence.next().await {
+        seen.push((name, data["action"].clone(), data["username"].clone()));
+    }
+    assert_eq!(
+        seen,
+        [
+            ("membership".to_string(), json!("join"), json!("ben")),
+            ("membership".to_string(), json!("leave"), json!("ann")),
+            ("membership".to_string(), json!("leave"), json!("ben")),
+        ]
+    );
+}
+
+#[rocket::async_test]
+async fn user_stats_are_for_their_owner_and_moderators() {
+    let client = client(json!({ "accounts": accounts() })).await;
+    let sent = form(&client, "/message", "room=lobby&username=alice&message=hi")
+        .header(bearer("alice-token"));
+    sent.dispatch().await;
+
+    let own = client
+        .get("/user-stats?username=alice")
+        .header(bearer("alice-token"))
+        .dispatch()
+        .await;
+    assert_eq!(own.into_json::<Value>().await.unwrap()["messages"], 1);
+    let moderated = client
+        .get("/user-stats?username=alice")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    assert_eq!(moderated.status(), Status::Ok);
+
+    let snooping = client
+        .get("/user-stats?username=alice")
+        .header(bearer("bob-token"))
+        .dispatch()
+        .await;
+    assert_eq!(snooping.status(), Status::Forbidden);
+    let anonymous = client.get("/user-stats?username=alice").dispatch().await;
+    assert_eq!(anonymous.status(), Status::Unauthorized);
+    let unknown = client
+        .get("/user-stats?username=bob")
+        .header(bearer("bob-token"))
+        .dispatch()
+        .await;
+    assert_eq!(unknown.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn history_ranges_jump_to_a_date() {
+    let client = client(json!({ "max_range_results": 3, "max_range_span": 60 })).await;
+    let mut stream = Events::get(&client, "/events").await;
+    for n in 0..5 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+    let stamps: Vec<_> = stream
+        .messages(5)
+        .await
+        .iter()
+        .map(|msg| msg["timestamp"].as_u64().unwrap())
+        .collect();
+
+    let uri = |from: u64, to: u64, limit: &str| {
+        format!("/history-range?room=lobby&from={}&to={}{}", from, to, limit)
+    };
+    let capped = get_json(&client, &uri(stamps[0], stamps[4], "&limit=10")).await;
+    assert_eq!(ids(capped["messages"].as_array().unwrap()), [1, 2, 3]);
+    assert_eq!(capped["has_more"], true);
+    let rest = get_json(&client, &uri(stamps[2] + 1, stamps[4], "")).await;
+    assert_eq!(ids(rest["messages"].as_array().unwrap()), [4, 5]);
+    assert_eq!(rest["has_more"], false);
+    let empty = get_json(&client, &uri(stamps[4] + 1, stamps[4] + 10, "")).await;
+    assert_eq!(empty, json!({ "messages": [], "has_more": false }));
+}
+
+#[rocket::async_test]
+async fn history_ranges_have_to_make_sense() {
+    let client = client(json!({ "max_range_span": 60 })).await;
+    let response = client
+        .get("/history-range?room=lobby&from=2000&to=1000")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: Value = response.into_json().await.unwrap();
+    assert_eq!(
+        body["errors"][0]["message"],
+        "the range has to end after it starts"
+    );
+
+    let widest = client
+        .get(format!("/history-range?room=lobby&from=0&to={}", u64::MAX))
+        .dispatch()
+        .await;
+    assert_eq!(widest.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn history_ranges_wider_than_allowed_end_early() {
+    let client = client(json!({ "max_range_span": 1, "max_range_results": 0 })).await;
+    let mut stream = Events::get(&client, "/events").await;
+    for n in 0..5 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+    let stamps: Vec<_> = stream
+        .messages(5)
+        .await
+        .iter()
+        .map(|msg| msg["timestamp"].as_u64().unwrap())
+        .collect();
+
+    let uri = |from: u64, to: u64| format!("/history-range?room=lobby&from={}&to={}", from, to);
+    let clamped = get_json(&client, &uri(stamps[1] - 1000, stamps[4])).await;
+    assert_eq!(ids(clamped["messages"].as_array().unwrap()), [1, 2]);
+    assert_eq!(clamped["has_more"], true);
+    let unlimited = get_json(&client, &uri(stamps[4] - 1000, stamps[4] + 5000)).await;
+    assert_eq!(
+        ids(unlimited["messages"].as_array().unwrap()),
+        [1, 2, 3, 4, 5]
+    );
+    assert_eq!(unlimited["has_more"], false);
+}
+
+#[rocket::async_test]
+async fn flagged_users_cool_down_and_keep_their_drafts() {
+    let client = client(json!({ "accounts": accounts(), "report_hide_threshold": 1 })).await;
+    let alice = |body: &str| form(&client, "/message", body).header(bearer("alice-token"));
+    alice("room=lobby&username=alice&message=spam")
+        .dispatch()
+        .await;
+    report(&client, "bob-token", "id=1").await;
+    assert_eq!(
+        alice("room=lobby&username=alice&message=sorry")
+            .dispatch()
+            .await
+            .status(),
+        Status::Ok
+    );
+
+    let draft = form(&client, "/draft", "room=lobby&message=more").header(bearer("alice-token"));
+    draft.dispatch().await;
+    let (status, body) = post_json(alice("room=lobby&username=alice&message=more")).await;
+    assert_eq!(status, Status::TooManyRequests);
+    assert_eq!(body["retry_after"], 10);
+
+    let kept = client
+        .get("/draft?room=lobby")
+        .header(bearer("alice-token"))
+        .dispatch()
+        .await;
+    assert_eq!(kept.into_json::<Value>().await.unwrap()["message"], "more");
+    assert_eq!(
+        post(&client, "room=lobby&username=ann&message=hi").await,
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn rooms_can_turn_a_feature_off() {
+    let rooms =
+        json!({ "quiet": { "features": { "role_badges": false, "read_receipts": false } } });
+    let client = client(json!({ "accounts": accounts(), "rooms": rooms })).await;
+    let mut stream = Events::get(&client, "/events").await;
+    for room in ["lobby", "quiet"] {
+        let body = format!("room={}&username=mod&message=hi", room);
+        form(&client, "/message", &body)
+            .header(bearer("mod-token"))
+            .dispatch()
+            .await;
+    }
+
+    let messages = stream.messages(2).await;
+    assert_eq!(messages[0]["role"], "moderator");
+    assert_eq!(messages[1]["role"], Value::Null);
+
+    form(&client, "/read", "room=lobby&id=2&username=ben")
+        .dispatch()
+        .await;
+    form(&client, "/read", "room=quiet&id=2&username=ben")
+        .dispatch()
+        .await;
+    assert_eq!(
+        stream.next_named("read_receipt").await.unwrap()["room"],
+        "lobby"
+    );
+    assert_eq!(stream.next_named("read_receipt").await, None);
+}
+
+#[rocket::async_test]
+async fn rooms_can_override_every_per_message_switch() {
+    let features = json!({
+        "content_hashes": false,
+        "byte_lengths": true,
+        "require_visible": false,
+        "resume": false,
+    });
+    let rooms = json!({ "plain": { "continuation_window": 0, "features": features } });
+    let client = client(json!({ "rooms": rooms })).await;
+    let mut stream = Events::get(&client, "/events").await;
+    for room in ["lobby", "lobby", "plain", "plain"] {
+        let body = format!("room={}&username=ann&message=hi", room);
+        assert_eq!(post(&client, &body).await, Status::Ok);
+    }
+
+    let messages = stream.messages(4).await;
+    for (lobby, plain) in [(&messages[0], &messages[2]), (&messages[1], &messages[3])] {
+        assert!(lobby["content_hash"].is_string());
+        assert_eq!(plain["content_hash"], Value::Null);
+        assert_eq!(lobby["byte_len"], Value::Null);
+        assert_eq!(plain["byte_len"], plain.to_string().len());
+    }
+    assert_eq!(messages[1]["continuation"], true);
+    assert_eq!(messages[3]["continuation"], false);
+
+    let blank = |room: &str| format!("room={}&username=ann&message=%E2%80%8B", room);
+    assert_eq!(
+        post(&client, &blank("lobby")).await,
+        Status::UnprocessableEntity
+    );
+    assert_eq!(post(&client, &blank("plain")).await, Status::Ok);
+
+    form(&client, "/read", "room=lobby&id=1").dispatch().await;
+    form(&client, "/read", "room=plain&id=3").dispatch().await;
+    let mut resumed = Events::get(&client, "/events").await;
+    assert_eq!(ids(&resumed.messages(3).await), [2]);
+}
+
+#[rocket::async_test]
+async fn large_replays_leave_the_runtime_responsive() {
+    let client =
+        client(json!({ "history_size": 3000, "max_backfill": 3000, "replay_batch": 16 })).await;
+    for n in 0..2000 {
+        post(&client, &format!("room=lobby&username=ann&message={}", n)).await;
+    }
+
+    // something else on the runtime, noting the longest it went unserved
+    let ticker = rocket::tokio::spawn(async {
+        let mut longest = Duration::ZERO;
+        let mut last = time::Instant::now();
+        for _ in 0..200 {
+            time::sleep(Duration::from_millis(1)).await;
+            longest = longest.max(last.elapsed());
+            last = time::Instant::now();
+        }
+        longest
+    });
+
+    let mut stream = Events::get(&client, "/events?backfill=3000").await;
+    let replayed = stream.messages(2000).await;
+    assert_eq!(replayed.len(), 2000);
+    assert_eq!(replayed.last().unwrap()["id"], 2000);
+    assert!(ticker.await.unwrap() < Duration::from_millis(250));
+}
+
+#[rocket::async_test]
+async fn byte_lengths_match_the_message_as_sent() {
+    let sized = client(json!({ "byte_lengths": true })).await;
+    let mut stream = Events::get(&sized, "/events").await;
+    for text in [
+        "plain",
+        "h%C3%A9llo",
+        "%F0%9F%91%8B%F0%9F%91%8B",
+        "%E6%97%A5%E6%9C%AC%E8%AA%9E",
+    ] {
+        post(&sized, &format!("room=lobby&username=ann&message={}", text)).await;
+    }
+
+    for message in stream.messages(4).await {
+        assert_eq!(message["byte_len"], message.to_string().len(), "{message}");
+    }
+
+    let plain = client(json!({})).await;
+    let mut stream = Events::get(&plain, "/events").await;
+    post(&plain, "room=lobby&username=ann&message=h%C3%A9llo").await;
+    assert_eq!(stream.messages(1).await[0].get("byte_len"), None);
+}
+
+#[rocket::async_test]
+async fn blank_messages_are_turned_away() {
+    let strict = client(json!({})).await;
+    for blank in ["%E2%80%8B%E2%80%8B", "%E2%80%AE", "%CC%81%CC%88", "+%09+"] {
+        let body = format!("room=lobby&username=ann&message={}", blank);
+        let (status, errors) = post_json(form(&strict, "/message", &body)).await;
+        assert_eq!(status, Status::UnprocessableEntity, "{blank}");
+        assert_eq!(errors["errors"][0]["field"], "message");
+    }
+    assert_eq!(
+        post(&strict, "room=lobby&username=ann&message=e%CC%81").await,
+        Status::Ok
+    );
+
+    let lenient = client(json!({ "require_visible": false })).await;
+    assert_eq!(
+        post(&lenient, "room=lobby&username=ann&message=%E2%80%8B").await,
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn the_effective_config_is_for_admins_without_tokens() {
+    let rooms = json!({ "standup": { "single_line": true } });
+    let client =
+        client(json!({ "accounts": accounts(), "max_backfill": 42, "rooms": rooms })).await;
+
+    let response = client
+        .get("/config")
+        .header(bearer("admin-token"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().await.unwrap();
+    assert!(!body.contains("-token"), "{body}");
+
+    let config: Value = json::from_str(&body).unwrap();
+    assert_eq!(config["max_backfill"], 42);
+    assert_eq!(config["rooms"]["standup"]["single_line"], true);
+    assert_eq!(
+        config["accounts"][0],
+        json!({ "username": "admin", "role": "admin" })
+    );
+    assert_eq!(config["accounts"].as_array().unwrap().len(), 4);
+
+    let anonymous = client.get("/config").dispatch().await;
+    assert_eq!(anonymous.status(), Status::Unauthorized);
+    let moderator = client
+        .get("/config")
+        .header(bearer("mod-token"))
+        .dispatch()
+        .await;
+    assert_eq!(moderator.status(), Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn lite_streams_leave_out_the_extras() {
+    let chat = json!({ "accounts": accounts(), "content_hashes": true, "byte_lengths": true });
+    let client = client(chat).await;
+    let mut full = Events::get(&client, "/events").await;
+    let mut lite = Events::get(&client, "/events?mode=lite").await;
+    let sent =
+        form(&client, "/message", "room=lobby&username=mod&message=hi").header(bearer("mod-token"));
+    sent.dispatch().await;
+    let copy = form(&client, "/crosspost", "id=1&room=dev").header(bearer("mod-token"));
+    assert_eq!(copy.dispatch().await.status(), Status::Ok);
+
+    let heavy = ["content_hash", "byte_len", "role", "crossposted_from"];
+    let full = full.messages(2).await;
+    for field in &heavy[..3] {
+        assert!(full[0].get(field).is_some(), "{field}");
+    }
+    assert!(full[1].get("crossposted_from").is_some());
+
+    for message in lite.messages(2).await {
+        for field in heavy {
+            assert!(message.get(field).is_none(), "{field}");
+        }
+        assert_eq!(message["message"], "hi");
+        assert_eq!(message["username"], "mod");
+    }
+}
@@ -0,0 +1,78 @@
+// what a message looks like once it's rendered, as opposed to what's in it
+
+use unicode_general_category::{get_general_category, GeneralCategory};
+
+// whether anything in `text` would show up on screen. whitespace, control
+// and format characters (zero-width spaces, direction overrides and the
+// like) and marks with nothing to sit on all render as blank, as do the few
+// letters that are really fillers
+pub fn visible(text: &str) -> bool {
+    text.chars().any(|c| !blank(c))
+}
+
+fn blank(c: char) -> bool {
+    use GeneralCategory::*;
+
+    matches!(
+        get_general_category(c),
+        Control
+            | Format
+            | SpaceSeparator
+            | LineSeparator
+            | ParagraphSeparator
+            | NonspacingMark
+            | SpacingMark
+            | EnclosingMark
+    ) || matches!(
+        c,
+        // hangul fillers and the blank braille pattern
+        '\u{115f}' | '\u{1160}' | '\u{3164}' | '\u{ffa0}' | '\u{2800}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_width_characters_are_blank() {
+        assert!(!visible("\u{200b}\u{200c}\u{200d}\u{2060}\u{feff}"));
+    }
+
+    #[test]
+    fn direction_overrides_are_blank() {
+        assert!(!visible("\u{202e}\u{202d}\u{2066}\u{2069}"));
+    }
+
+    #[test]
+    fn marks_with_nothing_to_sit_on_are_blank() {
+        assert!(!visible("\u{301}\u{308}\u{20dd}"));
+    }
+
+    #[test]
+    fn whitespace_controls_and_fillers_are_blank() {
+        assert!(!visible(""));
+        assert!(!visible(" \t\n\u{a0}\u{3000}\u{7}"));
+        assert!(!visible("\u{3164}\u{2800}"));
+    }
+
+    #[test]
+    fn anything_real_shows() {
+        assert!(visible("hi"));
+        assert!(visible("\u{200b}.\u{200b}"));
+        assert!(visible("e\u{301}"));
+        assert!(visible("👋"));
+        assert!(visible("\u{202e}olleh"));
+        assert!(visible("日本"));
+    }
+
+    // an unassigned code point may be one a newer client can draw, like an
+    // emoji added after these tables, and private use ones draw icon fonts
+    #[test]
+    fn unassigned_and_private_use_code_points_show() {
+        assert_eq!(get_general_category('\u{378}'), GeneralCategory::Unassigned);
+        assert!(visible("\u{378}"));
+        assert!(visible("\u{e000}"));
+        assert!(visible("\u{f8ff}"));
+    }
+}
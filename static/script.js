@@ -64,18 +64,22 @@ function changeRoom(name) {
     messagesDiv.removeChild(msg);
   });
 
-  STATE[name].forEach((data) => addMessage(name, data.username, data.message));
+  STATE[name].forEach((data) =>
+    addMessage(name, data.username, data.message, false, data.continuation)
+  );
 }
 
 // Add `message` from `username` to `room`. If `push`, then actually store the
-// message. If the current room is `room`, render the message.
-function addMessage(room, username, message, push = false) {
+// message. If the current room is `room`, render the message. Messages marked
+// as a `continuation` by the server are grouped under the previous header.
+function addMessage(room, username, message, push = false, continuation = false) {
   if (push) {
-    STATE[room].push({ username, message });
+    STATE[room].push({ username, message, continuation });
   }
 
   if (STATE.room == room) {
     var node = messageTemplate.content.cloneNode(true);
+    node.querySelector(".message").classList.toggle("continuation", continuation);
     node.querySelector(".message .username").textContent = username;
     node.querySelector(".message .username").style.color = hashColor(username);
     node.querySelector(".message .text").textContent = message;
@@ -83,6 +87,22 @@ function addMessage(room, username, message, push = false) {
   }
 }
 
+// Let the server know we've seen everything in `room` up to `id`, so it can
+// catch us up from there if we drop off. Reports are batched per room.
+var readTimers = {};
+function reportRead(room, id) {
+  clearTimeout(readTimers[room]);
+  readTimers[room] = setTimeout(() => {
+    // with a username it doubles as a read receipt
+    const params = { room, id };
+    if (usernameField.value) params.username = usernameField.value;
+    fetch("/read", {
+      method: "POST",
+      body: new URLSearchParams(params),
+    });
+  }, 1000);
+}
+
 // Subscribe to the event source at `uri` with exponential backoff reconnect.
 function subscribe(uri) {
   var retryTime = 1;
@@ -95,7 +115,8 @@ function subscribe(uri) {
       console.log("decoded data", JSON.stringify(JSON.parse(ev.data)));
       const msg = JSON.parse(ev.data);
       if (!"message" in msg || !"room" in msg || !"username" in msg) return;
-      addMessage(msg.room, msg.username, msg.message, true);
+      addMessage(msg.room, msg.username, msg.message, true, msg.continuation);
+      reportRead(msg.room, msg.id);
     });
 
     events.addEventListener("open", () => {